@@ -15,40 +15,52 @@
 
 use std::{
     collections::{BTreeMap, BTreeSet},
-    fmt, iter,
+    fmt,
+    future::Future,
+    iter,
+    sync::{Arc, RwLock as StdRwLock},
 };
 #[cfg(feature = "e2e-encryption")]
-use std::{ops::Deref, sync::Arc};
+use std::{
+    ops::Deref,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
 
 use eyeball::{SharedObservable, Subscriber};
 #[cfg(not(target_arch = "wasm32"))]
 use eyeball_im::{Vector, VectorDiff};
 #[cfg(not(target_arch = "wasm32"))]
 use futures_util::Stream;
-use matrix_sdk_common::instant::Instant;
+use matrix_sdk_common::{executor::spawn, instant::Instant};
 #[cfg(feature = "e2e-encryption")]
 use matrix_sdk_crypto::{
-    store::DynCryptoStore, EncryptionSettings, EncryptionSyncChanges, OlmError, OlmMachine,
-    ToDeviceRequest,
+    store::DynCryptoStore, CollectStrategy, EncryptionSettings, EncryptionSyncChanges, OlmError,
+    OlmMachine, ToDeviceRequest,
 };
 #[cfg(feature = "e2e-encryption")]
 use ruma::events::{
     room::{history_visibility::HistoryVisibility, message::MessageType},
     SyncMessageLikeEvent,
 };
-#[cfg(doc)]
-use ruma::DeviceId;
+#[cfg(feature = "experimental-sliding-sync")]
+use ruma::MilliSecondsSinceUnixEpoch;
 use ruma::{
     api::client as api,
     events::{
         ignored_user_list::IgnoredUserListEvent,
+        marked_unread::MarkedUnreadEventContent,
         push_rules::{PushRulesEvent, PushRulesEventContent},
         room::{
             member::{MembershipState, RoomMemberEventContent, SyncRoomMemberEvent},
             power_levels::{
-                RoomPowerLevelsEvent, RoomPowerLevelsEventContent, StrippedRoomPowerLevelsEvent,
+                RoomPowerLevels, RoomPowerLevelsEvent, RoomPowerLevelsEventContent,
+                StrippedRoomPowerLevelsEvent, SyncRoomPowerLevelsEvent,
             },
+            server_acl::{RoomServerAclEventContent, SyncRoomServerAclEvent},
+            tombstone::SyncRoomTombstoneEvent,
         },
+        tag::Tags,
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
         AnySyncEphemeralRoomEvent, AnySyncMessageLikeEvent, AnySyncStateEvent,
         AnySyncTimelineEvent, GlobalAccountDataEventType, StateEvent, StateEventType,
@@ -56,12 +68,17 @@ use ruma::{
     },
     push::{Action, PushConditionRoomCtx, Ruleset},
     serde::Raw,
-    OwnedRoomId, OwnedUserId, RoomId, RoomVersionId, UInt, UserId,
+    DeviceId, MxcUri, OwnedEventId, OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, RoomVersionId,
+    UInt, UserId,
 };
+#[cfg(target_arch = "wasm32")]
+use tokio::sync::oneshot;
 use tokio::sync::{broadcast, Mutex};
 #[cfg(feature = "e2e-encryption")]
-use tokio::sync::{RwLock, RwLockReadGuard};
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use tracing::{debug, info, instrument, trace, warn};
+#[cfg(feature = "e2e-encryption")]
+use tracing::{field, Span};
 
 #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
 use crate::latest_event::{is_suitable_for_latest_event, LatestEvent, PossibleLatestEvent};
@@ -70,15 +87,36 @@ use crate::RoomMemberships;
 use crate::{
     deserialized_responses::{RawAnySyncOrStrippedTimelineEvent, SyncTimelineEvent},
     error::{Error, Result},
-    rooms::{normal::RoomInfoUpdate, Room, RoomInfo, RoomState},
+    rooms::{
+        normal::RoomInfoUpdate, DisplayName, ReceiptUpdate, Room, RoomAvatarSource, RoomInfo,
+        RoomState, RoomStateCounts, RoomSummaryView,
+    },
     store::{
         ambiguity_map::AmbiguityCache, DynStateStore, MemoryStore, Result as StoreResult,
-        StateChanges, StateStoreDataKey, StateStoreDataValue, StateStoreExt, Store, StoreConfig,
+        StateChanges, StateChangesSummary, StateSnapshot, StateStoreDataKey, StateStoreDataValue,
+        StateStoreExt, Store, StoreConfig, StoreError, StoreErrorEvent, StoreValidationIssue,
+        StoreValidationReport,
     },
-    sync::{JoinedRoomUpdate, LeftRoomUpdate, Notification, RoomUpdates, SyncResponse, Timeline},
-    RoomStateFilter, SessionMeta,
+    sync::{
+        JoinedRoomUpdate, LeftRoomUpdate, Notification, RoomUpdates, SyncResponse, Timeline,
+        UnreadNotificationsCount,
+    },
+    MinimalRoomMemberEvent, MinimalStateEvent, RoomStateFilter, SessionMeta,
 };
 
+/// A handler for timeline events of a given type, registered with
+/// [`BaseClient::register_timeline_event_handler`].
+///
+/// It is called with the ID of the room the event was received in, and the raw
+/// event, after it has been decrypted if possible.
+pub type TimelineEventHandler = dyn Fn(&RoomId, &Raw<AnySyncTimelineEvent>) + Send + Sync;
+
+/// A function rewriting media URLs, e.g. to go through a media proxy.
+///
+/// It is called with the original `mxc://` URL and returns the URL to use
+/// instead, usually an HTTP URL.
+pub type MediaUrlRewriter = dyn Fn(&MxcUri) -> String + Send + Sync;
+
 /// A no IO Client implementation.
 ///
 /// This Client is a state machine that receives responses and events and
@@ -98,13 +136,86 @@ pub struct BaseClient {
     /// [`BaseClient::set_session_meta`]
     #[cfg(feature = "e2e-encryption")]
     olm_machine: Arc<RwLock<Option<OlmMachine>>>,
+    /// Whether encryption was disabled because the olm-machine couldn't be
+    /// created, see
+    /// [`BaseClient::with_encryption_disabled_on_crypto_store_error`].
+    #[cfg(feature = "e2e-encryption")]
+    encryption_disabled: Arc<AtomicBool>,
     /// Observable of when a user is ignored/unignored.
     pub(crate) ignore_user_list_changes: SharedObservable<Vec<String>>,
 
+    /// Observable of the sum of the unread notification counts of all rooms.
+    total_unread_counts: SharedObservable<UnreadNotificationsCount>,
+
     /// A sender that is used to communicate changes to room information. Each
     /// event contains the room and a boolean whether this event should
     /// trigger a room list update.
     pub(crate) roominfo_update_sender: broadcast::Sender<RoomInfoUpdate>,
+
+    /// A sender that is used to communicate a summary of the changes saved in
+    /// the store.
+    state_changes_sender: broadcast::Sender<StateChangesSummary>,
+
+    /// A sender that is used to communicate the users that are currently
+    /// typing in a room.
+    typing_sender: broadcast::Sender<(OwnedRoomId, Vec<OwnedUserId>)>,
+
+    /// A sender that is used to communicate the read receipts that have been
+    /// saved in the store.
+    receipts_sender: broadcast::Sender<ReceiptUpdate>,
+
+    /// A sender that is used to communicate the rooms that turned on
+    /// encryption.
+    room_encryption_enabled_sender: broadcast::Sender<OwnedRoomId>,
+
+    /// A sender that is used to communicate the rooms that were upgraded,
+    /// along with their successor.
+    room_upgrades_sender: broadcast::Sender<(OwnedRoomId, OwnedRoomId)>,
+
+    /// A sender that is used to communicate the users who were added to or
+    /// removed from the ignored user list.
+    ignored_user_effects_sender: broadcast::Sender<OwnedUserId>,
+
+    /// A sender that is used to communicate the errors that happened when
+    /// saving changes in the state store.
+    store_errors_sender: broadcast::Sender<StoreErrorEvent>,
+
+    /// A sender that is used to communicate the users whose devices changed.
+    #[cfg(feature = "e2e-encryption")]
+    device_list_changes_sender: broadcast::Sender<OwnedUserId>,
+
+    /// The handlers to call for timeline events of a given type, keyed by the
+    /// event type.
+    timeline_event_handlers: Arc<StdRwLock<BTreeMap<String, Vec<Arc<TimelineEventHandler>>>>>,
+
+    /// The function rewriting the media URLs received during sync, if any.
+    media_url_rewriter: Option<Arc<MediaUrlRewriter>>,
+
+    /// Whether the rooms we left should keep their final state, see
+    /// [`StoreConfig::retain_left_rooms_state`].
+    retain_left_rooms_state: bool,
+
+    /// Whether notifications should be left out of the response to the
+    /// initial sync.
+    suppress_initial_sync_notifications: bool,
+
+    /// Whether timeline events sent from servers denied by the room's server
+    /// ACL should be dropped.
+    drop_events_from_denied_servers: bool,
+
+    /// The strategy used to select the devices that should receive the room
+    /// keys of our outbound group sessions.
+    #[cfg(feature = "e2e-encryption")]
+    pub(crate) room_key_recipient_strategy: CollectStrategy,
+
+    /// Whether encryption should be disabled, instead of failing, when the
+    /// `OlmMachine` can't be created from the crypto store.
+    #[cfg(feature = "e2e-encryption")]
+    disable_encryption_on_crypto_store_error: bool,
+
+    /// Per-room overrides of the `room_key_recipient_strategy`.
+    #[cfg(feature = "e2e-encryption")]
+    room_key_recipient_strategy_overrides: Arc<StdRwLock<BTreeMap<OwnedRoomId, CollectStrategy>>>,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -131,6 +242,16 @@ impl BaseClient {
     ///   previous login call.
     pub fn with_store_config(config: StoreConfig) -> Self {
         let (roominfo_update_sender, _roominfo_update_receiver) = broadcast::channel(100);
+        let (state_changes_sender, _state_changes_receiver) = broadcast::channel(100);
+        let (typing_sender, _typing_receiver) = broadcast::channel(100);
+        let (receipts_sender, _receipts_receiver) = broadcast::channel(100);
+        let (room_encryption_enabled_sender, _room_encryption_enabled_receiver) =
+            broadcast::channel(100);
+        let (room_upgrades_sender, _room_upgrades_receiver) = broadcast::channel(100);
+        let (ignored_user_effects_sender, _ignored_user_effects_receiver) = broadcast::channel(100);
+        let (store_errors_sender, _store_errors_receiver) = broadcast::channel(100);
+        #[cfg(feature = "e2e-encryption")]
+        let (device_list_changes_sender, _device_list_changes_receiver) = broadcast::channel(100);
 
         BaseClient {
             store: Store::new(config.state_store),
@@ -138,20 +259,168 @@ impl BaseClient {
             crypto_store: config.crypto_store,
             #[cfg(feature = "e2e-encryption")]
             olm_machine: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            encryption_disabled: Default::default(),
             ignore_user_list_changes: Default::default(),
+            total_unread_counts: Default::default(),
             roominfo_update_sender,
+            state_changes_sender,
+            typing_sender,
+            receipts_sender,
+            room_encryption_enabled_sender,
+            room_upgrades_sender,
+            ignored_user_effects_sender,
+            store_errors_sender,
+            #[cfg(feature = "e2e-encryption")]
+            device_list_changes_sender,
+            timeline_event_handlers: Default::default(),
+            media_url_rewriter: None,
+            retain_left_rooms_state: config.retain_left_rooms_state,
+            suppress_initial_sync_notifications: false,
+            drop_events_from_denied_servers: false,
+            #[cfg(feature = "e2e-encryption")]
+            room_key_recipient_strategy: Default::default(),
+            #[cfg(feature = "e2e-encryption")]
+            disable_encryption_on_crypto_store_error: false,
+            #[cfg(feature = "e2e-encryption")]
+            room_key_recipient_strategy_overrides: Default::default(),
+        }
+    }
+
+    /// Set whether the notifications should be left out of the
+    /// [`SyncResponse`] of the initial sync, i.e. the first sync without a
+    /// sync token.
+    ///
+    /// The initial sync contains the backlog of the rooms, which clients
+    /// usually don't want to notify about. The push actions of the timeline
+    /// events are still computed.
+    ///
+    /// Defaults to `false`.
+    pub fn with_initial_sync_notifications_suppressed(mut self, suppress: bool) -> Self {
+        self.suppress_initial_sync_notifications = suppress;
+        self
+    }
+
+    /// Set whether the timeline events whose sender belongs to a server
+    /// denied by the `m.room.server_acl` state event of the room should be
+    /// dropped.
+    ///
+    /// Homeservers should already reject these events, but this allows to
+    /// enforce the ACL locally if a server relays them anyway. Dropped events
+    /// are not part of the [`SyncResponse`], don't trigger notifications and
+    /// aren't stored, so the local view of the room may diverge from the one
+    /// of the homeserver and of other clients, including for the state events
+    /// of the timeline.
+    ///
+    /// Defaults to `false`.
+    pub fn with_events_from_denied_servers_dropped(mut self, drop: bool) -> Self {
+        self.drop_events_from_denied_servers = drop;
+        self
+    }
+
+    /// Set a function to rewrite media URLs, e.g. when the media are accessed
+    /// through a proxy.
+    ///
+    /// The URLs received during sync are stored as-is, the function is applied
+    /// when they are read with [`BaseClient::rewrite_media_url`].
+    pub fn with_media_url_rewriter<F>(mut self, rewriter: F) -> Self
+    where
+        F: Fn(&MxcUri) -> String + Send + Sync + 'static,
+    {
+        self.media_url_rewriter = Some(Arc::new(rewriter));
+        self
+    }
+
+    /// Set the strategy used to select the devices that should receive the
+    /// room keys when sharing them with the members of an encrypted room.
+    ///
+    /// Defaults to sharing room keys with all the devices of the members.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn with_room_key_recipient_strategy(mut self, strategy: CollectStrategy) -> Self {
+        self.room_key_recipient_strategy = strategy;
+        self
+    }
+
+    /// Set whether encryption should be disabled, rather than failing, when
+    /// the `OlmMachine` can't be created from the crypto store in
+    /// [`BaseClient::set_session_meta`].
+    ///
+    /// This allows to use the client for unencrypted rooms even if the
+    /// crypto store is corrupted, but the encrypted rooms won't be usable and
+    /// [`BaseClient::olm_machine`] will return `None`, without trying to
+    /// create the `OlmMachine` again, until [`BaseClient::regenerate_olm`]
+    /// succeeds.
+    ///
+    /// Defaults to `false`.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn with_encryption_disabled_on_crypto_store_error(mut self, disable: bool) -> Self {
+        self.disable_encryption_on_crypto_store_error = disable;
+        self
+    }
+
+    /// Override the strategy used to select the devices that should receive
+    /// the room keys for the given room.
+    ///
+    /// Passing `None` removes the override, so the strategy set with
+    /// [`BaseClient::with_room_key_recipient_strategy`] is used again.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn set_room_key_recipient_strategy_for_room(
+        &self,
+        room_id: &RoomId,
+        strategy: Option<CollectStrategy>,
+    ) {
+        let mut overrides = self.room_key_recipient_strategy_overrides.write().unwrap();
+
+        match strategy {
+            Some(strategy) => {
+                overrides.insert(room_id.to_owned(), strategy);
+            }
+            None => {
+                overrides.remove(room_id);
+            }
         }
     }
 
+    /// Get the strategy used to select the devices that should receive the
+    /// room keys for the given room.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn room_key_recipient_strategy_for_room(&self, room_id: &RoomId) -> CollectStrategy {
+        self.room_key_recipient_strategy_overrides
+            .read()
+            .unwrap()
+            .get(room_id)
+            .unwrap_or(&self.room_key_recipient_strategy)
+            .clone()
+    }
+
     /// Clones the current base client to use the same crypto store but a
     /// different, in-memory store config, and resets transient state.
     pub fn clone_with_in_memory_state_store(&self) -> Self {
-        let config = StoreConfig::new().state_store(MemoryStore::new());
+        let config = StoreConfig::new()
+            .state_store(MemoryStore::new())
+            .retain_left_rooms_state(self.retain_left_rooms_state);
 
         #[cfg(feature = "e2e-encryption")]
         let config = config.crypto_store(self.crypto_store.clone());
 
-        Self::with_store_config(config)
+        let mut client = Self::with_store_config(config)
+            .with_initial_sync_notifications_suppressed(self.suppress_initial_sync_notifications)
+            .with_events_from_denied_servers_dropped(self.drop_events_from_denied_servers);
+        client.media_url_rewriter = self.media_url_rewriter.clone();
+
+        #[cfg(feature = "e2e-encryption")]
+        let client = {
+            let client = client
+                .with_room_key_recipient_strategy(self.room_key_recipient_strategy.clone())
+                .with_encryption_disabled_on_crypto_store_error(
+                    self.disable_encryption_on_crypto_store_error,
+                );
+            *client.room_key_recipient_strategy_overrides.write().unwrap() =
+                self.room_key_recipient_strategy_overrides.read().unwrap().clone();
+            client
+        };
+
+        client
     }
 
     /// Get the session meta information.
@@ -163,11 +432,89 @@ impl BaseClient {
         self.store.session_meta()
     }
 
+    /// Get the user ID of the session, if the client is logged in.
+    pub fn user_id(&self) -> Option<&UserId> {
+        self.session_meta().map(|meta| meta.user_id.as_ref())
+    }
+
+    /// Get the device ID of the session, if the client is logged in.
+    pub fn device_id(&self) -> Option<&DeviceId> {
+        self.session_meta().map(|meta| meta.device_id.as_ref())
+    }
+
     /// Get all the rooms this client knows about.
     pub fn rooms(&self) -> Vec<Room> {
         self.store.rooms()
     }
 
+    /// Get a page of the rooms this client knows about.
+    ///
+    /// Only the rooms of the requested page are cloned, which is useful to
+    /// display a virtualized room list. The rooms are in the same order as in
+    /// [`BaseClient::rooms`].
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The number of rooms to skip.
+    ///
+    /// * `limit` - The maximum number of rooms to return.
+    pub fn rooms_paginated(&self, offset: usize, limit: usize) -> Vec<Room> {
+        self.store.rooms_paginated(offset, limit)
+    }
+
+    /// Whether the members of the room with the given ID need to be requested
+    /// again with `/members`, e.g. because they were marked as missing after a
+    /// limited sync.
+    ///
+    /// Returns `false` if the room is unknown.
+    pub fn needs_member_resync(&self, room_id: &RoomId) -> bool {
+        self.store.room(room_id).is_some_and(|room| !room.are_members_synced())
+    }
+
+    /// Get the ID of the latest event of the room with the given ID, if any.
+    ///
+    /// See [`Room::latest_event_id`].
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn latest_event_id(&self, room_id: &RoomId) -> Option<OwnedEventId> {
+        self.store.room(room_id)?.latest_event_id()
+    }
+
+    /// Get the timestamp of the latest event of the room with the given ID, if
+    /// any.
+    ///
+    /// This is useful to sort rooms by activity. See
+    /// [`Room::latest_event_timestamp`].
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn latest_event_timestamp(&self, room_id: &RoomId) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.store.room(room_id)?.latest_event_timestamp()
+    }
+
+    /// Get up to `limit` rooms, sorted by the timestamp of their latest
+    /// event, most recent first.
+    ///
+    /// Rooms without a latest event are sorted last.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn rooms_by_recent_activity(&self, limit: usize) -> Vec<Room> {
+        let mut rooms: Vec<_> =
+            self.rooms().into_iter().map(|room| (room.latest_event_timestamp(), room)).collect();
+
+        rooms.sort_by(|(a, _), (b, _)| b.cmp(a));
+        rooms.into_iter().take(limit).map(|(_, room)| room).collect()
+    }
+
+    /// Get the number of rooms this client knows about.
+    pub fn room_count(&self) -> usize {
+        self.store.room_count()
+    }
+
+    /// Count the rooms this client knows about, grouped by room state.
+    ///
+    /// This only goes over the rooms once, unlike calling
+    /// [`BaseClient::rooms_filtered`] for every state.
+    pub fn room_counts(&self) -> RoomStateCounts {
+        self.store.room_counts()
+    }
+
     /// Get all the rooms this client knows about, filtered by room state.
     pub fn rooms_filtered(&self, filter: RoomStateFilter) -> Vec<Room> {
         self.store.rooms_filtered(filter)
@@ -216,6 +563,11 @@ impl BaseClient {
     ///   useful if one wishes to create identity keys before knowing the
     ///   user/device IDs, e.g., to use the identity key as the device ID.
     ///
+    /// If the `OlmMachine` can't be created and
+    /// [`BaseClient::with_encryption_disabled_on_crypto_store_error`] was
+    /// enabled, a warning is logged and encryption stays disabled instead of
+    /// returning an error.
+    ///
     /// This method panics if it is called twice.
     pub async fn set_session_meta(
         &self,
@@ -227,12 +579,34 @@ impl BaseClient {
         debug!(user_id = ?session_meta.user_id, device_id = ?session_meta.device_id, "Restoring login");
         self.store.set_session_meta(session_meta.clone(), &self.roominfo_update_sender).await?;
 
+        // Sum the counts of the restored rooms, they are then summed again in
+        // `apply_changes` when they change.
+        self.total_unread_counts.set_if_not_eq(self.total_unread_counts());
+
         #[cfg(feature = "e2e-encryption")]
-        self.regenerate_olm(custom_account).await?;
+        if let Err(error) = self.regenerate_olm(custom_account).await {
+            if !self.disable_encryption_on_crypto_store_error {
+                return Err(error);
+            }
+
+            warn!("Couldn't create the OlmMachine, encryption is disabled: {error}");
+            self.encryption_disabled.store(true, Ordering::SeqCst);
+        }
 
         Ok(())
     }
 
+    /// Whether a sync token was restored from the store by
+    /// [`BaseClient::set_session_meta`].
+    ///
+    /// This allows to know whether the client is doing a warm start, with the
+    /// state of previous syncs, or a cold start that needs a full initial
+    /// sync. It doesn't change once the session meta is set, and is `false`
+    /// before.
+    pub fn had_prior_sync(&self) -> bool {
+        self.store.had_prior_sync()
+    }
+
     /// Recreate an `OlmMachine` from scratch.
     ///
     /// In particular, this will clear all its caches.
@@ -242,12 +616,24 @@ impl BaseClient {
         custom_account: Option<crate::crypto::vodozemac::olm::Account>,
     ) -> Result<()> {
         tracing::debug!("regenerating OlmMachine");
+        let mut olm_machine = self.olm_machine.write().await;
+        self.regenerate_olm_locked(&mut olm_machine, custom_account).await
+    }
+
+    /// Recreate an `OlmMachine` from scratch, while the lock of the current
+    /// one is held.
+    #[cfg(feature = "e2e-encryption")]
+    async fn regenerate_olm_locked(
+        &self,
+        olm_machine: &mut RwLockWriteGuard<'_, Option<OlmMachine>>,
+        custom_account: Option<crate::crypto::vodozemac::olm::Account>,
+    ) -> Result<()> {
         let session_meta = self.session_meta().ok_or(Error::OlmError(OlmError::MissingSession))?;
 
         // Recreate the `OlmMachine` and wipe the in-memory cache in the store
         // because we suspect it has stale data.
         self.crypto_store.clear_caches().await;
-        let olm_machine = OlmMachine::with_store(
+        let new_olm_machine = OlmMachine::with_store(
             &session_meta.user_id,
             &session_meta.device_id,
             self.crypto_store.clone(),
@@ -256,16 +642,40 @@ impl BaseClient {
         .await
         .map_err(OlmError::from)?;
 
-        *self.olm_machine.write().await = Some(olm_machine);
+        **olm_machine = Some(new_olm_machine);
+        self.encryption_disabled.store(false, Ordering::SeqCst);
         Ok(())
     }
 
+    /// Whether encryption was disabled because the `OlmMachine` couldn't be
+    /// created from the crypto store, see
+    /// [`BaseClient::with_encryption_disabled_on_crypto_store_error`].
+    #[cfg(feature = "e2e-encryption")]
+    pub fn is_encryption_disabled(&self) -> bool {
+        self.encryption_disabled.load(Ordering::SeqCst)
+    }
+
     /// Get the current, if any, sync token of the client.
     /// This will be None if the client didn't sync at least once.
     pub async fn sync_token(&self) -> Option<String> {
         self.store.sync_token.read().await.clone()
     }
 
+    /// Forget the current sync token, in memory and in the store.
+    ///
+    /// The next sync will be an initial sync, which is useful to recover from
+    /// a corrupted state without clearing the whole store. The known rooms
+    /// and their state are kept, but the timelines received by the next sync
+    /// will be limited, and the members of the rooms will have to be fetched
+    /// again.
+    pub async fn reset_sync_token(&self) -> Result<()> {
+        let _sync_lock = self.sync_lock().lock().await;
+        self.store.remove_kv_data(StateStoreDataKey::SyncToken).await?;
+        *self.store.sync_token.write().await = None;
+
+        Ok(())
+    }
+
     #[cfg(feature = "e2e-encryption")]
     async fn handle_verification_event(
         &self,
@@ -280,7 +690,12 @@ impl BaseClient {
         Ok(())
     }
 
+    /// Attempt to decrypt the given event.
+    ///
+    /// The time spent decrypting and whether the event was unable to be
+    /// decrypted are recorded on a `trace` level span.
     #[cfg(feature = "e2e-encryption")]
+    #[instrument(skip_all, level = "trace", fields(?room_id, event_id, utd, elapsed))]
     async fn decrypt_sync_room_event(
         &self,
         event: &Raw<AnySyncTimelineEvent>,
@@ -289,8 +704,18 @@ impl BaseClient {
         let olm = self.olm_machine().await;
         let Some(olm) = olm.as_ref() else { return Ok(None) };
 
-        let event: SyncTimelineEvent =
-            olm.decrypt_room_event(event.cast_ref(), room_id).await?.into();
+        let span = Span::current();
+        if let Ok(Some(event_id)) = event.get_field::<OwnedEventId>("event_id") {
+            span.record("event_id", field::debug(&event_id));
+        }
+
+        let start = Instant::now();
+        let decrypted = olm.decrypt_room_event(event.cast_ref(), room_id).await;
+        span.record("elapsed", field::debug(start.elapsed()));
+        span.record("utd", decrypted.is_err());
+        trace!("Attempted to decrypt a room event");
+
+        let event: SyncTimelineEvent = decrypted?.into();
 
         if let Ok(AnySyncTimelineEvent::MessageLike(e)) = event.event.deserialize() {
             match &e {
@@ -325,17 +750,40 @@ impl BaseClient {
         changes: &mut StateChanges,
         notifications: &mut BTreeMap<OwnedRoomId, Vec<Notification>>,
         ambiguity_cache: &mut AmbiguityCache,
+        #[cfg(feature = "e2e-encryption")] decryption_time: &mut Duration,
     ) -> Result<Timeline> {
         let mut timeline = Timeline::new(limited, prev_batch);
         let mut push_context = self.get_push_room_context(room, room_info, changes).await?;
+        let mut server_acl = if self.drop_events_from_denied_servers {
+            self.get_server_acl(room.room_id(), changes).await?
+        } else {
+            None
+        };
 
         for event in events {
+            if let Some(server_acl) = &server_acl {
+                if let Ok(Some(sender)) = event.get_field::<OwnedUserId>("sender") {
+                    if !server_acl.is_allowed(sender.server_name()) {
+                        trace!(?sender, "Dropping an event sent from a denied server");
+                        continue;
+                    }
+                }
+            }
+
             let mut event: SyncTimelineEvent = event.into();
 
             match event.event.deserialize() {
                 Ok(e) => {
                     #[allow(clippy::single_match)]
                     match &e {
+                        // The same state event can be in the state and the timeline of a
+                        // sync response, don't apply it twice.
+                        AnySyncTimelineEvent::State(s)
+                            if is_state_event_in_changes(changes, room.room_id(), s) =>
+                        {
+                            trace!(event_id = ?s.event_id(), "Skipping an already applied state event");
+                        }
+
                         AnySyncTimelineEvent::State(s) => {
                             match s {
                                 AnySyncStateEvent::RoomMember(member) => {
@@ -346,6 +794,11 @@ impl BaseClient {
                                     ))
                                     .await?;
 
+                                    if member.state_key() == room.own_user_id() {
+                                        room_info.own_membership =
+                                            Some(member.membership().clone());
+                                    }
+
                                     match member.membership() {
                                         MembershipState::Join | MembershipState::Invite => {
                                             user_ids.insert(member.state_key().to_owned());
@@ -366,6 +819,14 @@ impl BaseClient {
                                 }
                             }
 
+                            if let AnySyncStateEvent::RoomServerAcl(SyncStateEvent::Original(acl)) =
+                                s
+                            {
+                                if self.drop_events_from_denied_servers {
+                                    server_acl = Some(acl.content.clone());
+                                }
+                            }
+
                             let raw_event: Raw<AnySyncStateEvent> = event.event.clone().cast();
                             changes.add_state_event(room.room_id(), s.clone(), raw_event);
                         }
@@ -389,11 +850,14 @@ impl BaseClient {
                             AnySyncMessageLikeEvent::RoomEncrypted(
                                 SyncMessageLikeEvent::Original(_),
                             ) => {
-                                if let Ok(Some(e)) = Box::pin(
+                                let start = Instant::now();
+                                let decrypted = Box::pin(
                                     self.decrypt_sync_room_event(&event.event, room.room_id()),
                                 )
-                                .await
-                                {
+                                .await;
+                                *decryption_time += start.elapsed();
+
+                                if let Ok(Some(e)) = decrypted {
                                     event = e;
                                 }
                             }
@@ -448,12 +912,54 @@ impl BaseClient {
                 }
             }
 
+            // The handlers are only called once the changes have been saved.
+            if !self.handlers_for_timeline_event(&event.event).is_empty() {
+                changes.add_timeline_event(room.room_id(), event.event.clone());
+            }
+
             timeline.events.push(event);
         }
 
         Ok(timeline)
     }
 
+    /// Get the server ACL of the room, from the given changes or the store.
+    async fn get_server_acl(
+        &self,
+        room_id: &RoomId,
+        changes: &StateChanges,
+    ) -> Result<Option<RoomServerAclEventContent>> {
+        if let Some(event) = changes.state.get(room_id).and_then(|types| {
+            types
+                .get(&StateEventType::RoomServerAcl)?
+                .get("")?
+                .deserialize_as::<SyncRoomServerAclEvent>()
+                .ok()
+        }) {
+            return Ok(event.as_original().map(|event| event.content.clone()));
+        }
+
+        Ok(self
+            .store
+            .get_state_event_static::<RoomServerAclEventContent>(room_id)
+            .await?
+            .and_then(|event| event.deserialize().ok())
+            .and_then(|event| event.as_sync()?.as_original().map(|event| event.content.clone())))
+    }
+
+    /// Get the handlers registered for the type of the given timeline event.
+    fn handlers_for_timeline_event(
+        &self,
+        event: &Raw<AnySyncTimelineEvent>,
+    ) -> Vec<Arc<TimelineEventHandler>> {
+        let Ok(Some(event_type)) = event.get_field::<String>("type") else {
+            return Vec::new();
+        };
+
+        // Clone the handlers so the lock isn't held while they are called.
+        self.timeline_event_handlers.read().unwrap().get(&event_type).cloned().unwrap_or_default()
+    }
+
     #[instrument(skip_all, fields(room_id = ?room_info.room_id))]
     pub(crate) async fn handle_invited_state(
         &self,
@@ -470,6 +976,17 @@ impl BaseClient {
             match raw_event.deserialize() {
                 Ok(e) => {
                     room_info.handle_stripped_state_event(&e);
+
+                    if let AnyStrippedStateEvent::RoomMember(member) = &e {
+                        if member.state_key == room.own_user_id() {
+                            room_info.own_membership = Some(member.content.membership.clone());
+
+                            if member.content.membership == MembershipState::Invite {
+                                room_info.inviter = Some(member.sender.clone());
+                            }
+                        }
+                    }
+
                     state_events
                         .entry(e.event_type())
                         .or_insert_with(BTreeMap::new)
@@ -531,6 +1048,10 @@ impl BaseClient {
             if let AnySyncStateEvent::RoomMember(member) = &event {
                 ambiguity_cache.handle_event(changes, &room_info.room_id, member).await?;
 
+                if self.user_id().is_some_and(|user_id| member.state_key() == user_id) {
+                    room_info.own_membership = Some(member.membership().clone());
+                }
+
                 match member.membership() {
                     MembershipState::Join | MembershipState::Invite => {
                         user_ids.insert(member.state_key().to_owned());
@@ -604,12 +1125,43 @@ impl BaseClient {
                     AnyRoomAccountDataEvent::Tag(event) => {
                         on_room_info(room_id, changes, self, |room_info| {
                             room_info.base_info.handle_notable_tags(&event.content.tags);
+                            room_info.base_info.handle_tag_orders(&event.content.tags);
                         });
                     }
 
                     // Nothing.
                     _ => {}
                 }
+            } else if is_empty_account_data(raw_event) {
+                // Account data is cleared by setting an empty content, which can't be
+                // deserialized for the events with required fields, so the state that
+                // was derived from them must be reset manually.
+                let Ok(Some(event_type)) = raw_event.get_field::<String>("type") else {
+                    continue;
+                };
+
+                match event_type.as_str() {
+                    "m.tag" => {
+                        on_room_info(room_id, changes, self, |room_info| {
+                            room_info.base_info.handle_notable_tags(&Tags::new());
+                            room_info.base_info.handle_tag_orders(&Tags::new());
+                        });
+                    }
+
+                    "m.marked_unread" | "com.famedly.marked_unread" => {
+                        on_room_info(room_id, changes, self, |room_info| {
+                            room_info.base_info.is_marked_unread = false;
+                        });
+                    }
+
+                    _ => continue,
+                }
+
+                changes
+                    .room_account_data
+                    .entry(room_id.to_owned())
+                    .or_default()
+                    .insert(event_type.into(), raw_event.clone());
             }
         }
     }
@@ -663,10 +1215,14 @@ impl BaseClient {
     pub(crate) async fn preprocess_to_device_events(
         &self,
         encryption_sync_changes: EncryptionSyncChanges<'_>,
-        #[cfg(feature = "experimental-sliding-sync")] changes: &mut StateChanges,
-        #[cfg(not(feature = "experimental-sliding-sync"))] _changes: &mut StateChanges,
+        changes: &mut StateChanges,
     ) -> Result<Vec<Raw<ruma::events::AnyToDeviceEvent>>> {
         if let Some(o) = self.olm_machine().await.as_ref() {
+            // The subscribers are only notified once the changes have been saved.
+            changes
+                .changed_devices
+                .extend(encryption_sync_changes.changed_devices.changed.iter().cloned());
+
             // Let the crypto machine handle the sync response, this
             // decrypts to-device events, but leaves room events alone.
             // This makes sure that we have the decryption keys for the room
@@ -692,6 +1248,31 @@ impl BaseClient {
         }
     }
 
+    /// Decrypt a single to-device event, for example one that was received
+    /// out-of-band instead of in a sync response.
+    ///
+    /// The event is handled by the [`OlmMachine`] like a to-device event
+    /// received in a sync response, which means that the room keys it might
+    /// contain are saved, but the one-time key counts and the device lists are
+    /// left untouched.
+    ///
+    /// Returns the decrypted event, or the event as-is if it's not encrypted or
+    /// if it couldn't be decrypted.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn decrypt_to_device_event(
+        &self,
+        event: Raw<ruma::events::AnyToDeviceEvent>,
+    ) -> Result<Raw<ruma::events::AnyToDeviceEvent>> {
+        let olm = self.olm_machine().await;
+        let Some(o) = olm.as_ref() else {
+            return Ok(event);
+        };
+
+        let (event, _room_key_updates) = o.receive_out_of_band_to_device_event(event).await?;
+
+        Ok(event)
+    }
+
     /// Decrypt any of this room's latest_encrypted_events
     /// that we can and if we can, change latest_event to reflect what we
     /// found, and remove any older encrypted events from
@@ -706,6 +1287,39 @@ impl BaseClient {
         }
     }
 
+    /// Try again to decrypt the latest encrypted events of all the rooms.
+    ///
+    /// This is useful after new room keys have been received outside of a
+    /// sync, for example after restoring a key backup, since the latest
+    /// events are only decrypted automatically when the keys come in during a
+    /// sync.
+    ///
+    /// Returns the IDs of the rooms whose latest event was updated.
+    #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
+    pub async fn refresh_all_latest_events(&self) -> Result<Vec<OwnedRoomId>> {
+        // Take the lock before computing the changes, so a sync can't update the
+        // rooms in the meantime and have its changes overwritten by ours.
+        let _sync_lock = self.sync_lock().lock().await;
+        let mut changes = StateChanges::default();
+
+        for room in self.store.rooms() {
+            if room.latest_encrypted_events().is_empty() {
+                continue;
+            }
+
+            self.decrypt_latest_events(&room, &mut changes).await;
+        }
+
+        if changes.room_infos.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.save_changes(&changes, "refresh_all_latest_events").await?;
+        self.apply_changes(&changes, false);
+
+        Ok(changes.room_infos.into_keys().collect())
+    }
+
     /// Attempt to decrypt a latest event, trying the latest stored encrypted
     /// one first, and walking backwards, stopping when we find an event
     /// that we can decrypt, and that is suitable to be the latest event
@@ -766,7 +1380,7 @@ impl BaseClient {
             room_info.mark_members_missing(); // the own member event changed
             let mut changes = StateChanges::default();
             changes.add_room(room_info.clone());
-            self.store.save_changes(&changes).await?; // Update the store
+            self.save_changes(&changes, "room_joined").await?; // Update the store
             room.set_room_info(room_info, false); // Update the cached room
                                                   // handle
         }
@@ -789,11 +1403,14 @@ impl BaseClient {
 
             let mut room_info = room.clone_info();
             room_info.mark_as_left();
-            room_info.mark_state_partially_synced();
-            room_info.mark_members_missing(); // the own member event changed
+            if !self.retain_left_rooms_state {
+                room_info.mark_state_partially_synced();
+                room_info.mark_members_missing(); // the own member event
+                                                  // changed
+            }
             let mut changes = StateChanges::default();
             changes.add_room(room_info.clone());
-            self.store.save_changes(&changes).await?; // Update the store
+            self.save_changes(&changes, "room_left").await?; // Update the store
             room.set_room_info(room_info, false); // Update the cached room
                                                   // handle
         }
@@ -801,43 +1418,187 @@ impl BaseClient {
         Ok(())
     }
 
-    /// Get access to the store's sync lock.
-    pub fn sync_lock(&self) -> &Mutex<()> {
-        self.store.sync_lock()
-    }
-
-    /// Receive a response from a sync call.
+    /// Receive global account data events outside of a sync response.
+    ///
+    /// The events are processed like the account data of a sync response,
+    /// saved in the store and applied, without changing the sync token. This
+    /// allows to apply settings, like the push rules, without a full sync.
     ///
     /// # Arguments
     ///
-    /// * `response` - The response that we received after a successful sync.
-    #[instrument(skip_all)]
-    pub async fn receive_sync_response(
+    /// * `events` - The global account data events to process.
+    pub async fn receive_account_data(
         &self,
-        response: api::sync::sync_events::v3::Response,
-    ) -> Result<SyncResponse> {
-        // The server might respond multiple times with the same sync token, in
-        // that case we already received this response and there's nothing to
-        // do.
-        if self.store.sync_token.read().await.as_ref() == Some(&response.next_batch) {
-            info!("Got the same sync response twice");
-            return Ok(SyncResponse::default());
-        }
+        events: &[Raw<AnyGlobalAccountDataEvent>],
+    ) -> Result<()> {
+        // Take the lock before handling the events, so a sync can't update the
+        // account data in the meantime and have its changes overwritten by ours.
+        let _sync_lock = self.sync_lock().lock().await;
 
-        let now = Instant::now();
-        let mut changes = Box::new(StateChanges::new(response.next_batch.clone()));
+        let mut changes = StateChanges::default();
+        self.handle_account_data(events, &mut changes).await;
 
-        #[cfg(feature = "e2e-encryption")]
-        let to_device = self
-            .preprocess_to_device_events(
-                EncryptionSyncChanges {
-                    to_device_events: response.to_device.events,
-                    changed_devices: &response.device_lists,
-                    one_time_keys_counts: &response.device_one_time_keys_count,
-                    unused_fallback_keys: response.device_unused_fallback_key_types.as_deref(),
-                    next_batch_token: Some(response.next_batch.clone()),
-                },
-                &mut changes,
+        self.save_changes(&changes, "receive_account_data").await?;
+        self.apply_changes(&changes, false);
+
+        Ok(())
+    }
+
+    /// Clear the unread marker of all the rooms that are marked as unread.
+    ///
+    /// The rooms are updated locally right away. This returns the
+    /// `m.marked_unread` room account data contents that must be uploaded to
+    /// the server for each affected room, for the change to be persisted on
+    /// the server side.
+    pub async fn clear_all_unread_markers(
+        &self,
+    ) -> Result<Vec<(OwnedRoomId, MarkedUnreadEventContent)>> {
+        let _sync_lock = self.sync_lock().lock().await;
+
+        let mut changes = StateChanges::default();
+        let mut contents = Vec::new();
+
+        for room in self.store.rooms() {
+            if !room.is_marked_unread() {
+                continue;
+            }
+
+            let mut room_info = room.clone_info();
+            room_info.base_info.is_marked_unread = false;
+            changes.add_room(room_info);
+
+            contents.push((room.room_id().to_owned(), MarkedUnreadEventContent::new(false)));
+        }
+
+        if !contents.is_empty() {
+            self.save_changes(&changes, "clear_all_unread_markers").await?;
+            self.apply_changes(&changes, true);
+        }
+
+        Ok(contents)
+    }
+
+    /// Set the name of a room locally, before the server sends the new
+    /// `m.room.name` state event.
+    ///
+    /// This is meant to be called after the room was renamed, so that the new
+    /// name is displayed right away. The room's display name is recomputed. The
+    /// local name is replaced by the one of the `m.room.name` state event as
+    /// soon as it's received.
+    ///
+    /// The local name is only kept in memory and is never saved in the store,
+    /// so it's dropped when the client is restarted, e.g. if the server never
+    /// sends the new `m.room.name` state event. [`Room::name`] still returns
+    /// the name of the current `m.room.name` state event.
+    ///
+    /// Returns the new display name of the room, or `None` if the room is
+    /// unknown.
+    pub async fn set_room_name_local(
+        &self,
+        room_id: &RoomId,
+        name: String,
+    ) -> Result<Option<DisplayName>> {
+        let Some(room) = self.store.room(room_id) else {
+            return Ok(None);
+        };
+
+        Ok(Some(room.set_local_name(name).await?))
+    }
+
+    /// Export a snapshot of the state of this client.
+    ///
+    /// It contains the sync token and the info of all the rooms, and can be
+    /// imported in another client with [`BaseClient::import_state_snapshot`].
+    pub async fn export_state_snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            sync_token: self.sync_token().await,
+            room_infos: self.store.rooms().iter().map(Room::clone_info).collect(),
+        }
+    }
+
+    /// Import a snapshot of the state of a client, that was exported with
+    /// [`BaseClient::export_state_snapshot`].
+    ///
+    /// The rooms of the snapshot replace the ones with the same ID known by
+    /// this client, and the sync token is replaced too.
+    pub async fn import_state_snapshot(&self, snapshot: StateSnapshot) -> Result<()> {
+        let mut changes =
+            StateChanges { sync_token: snapshot.sync_token.clone(), ..Default::default() };
+
+        for room_info in snapshot.room_infos {
+            changes.add_room(room_info);
+        }
+
+        let _sync_lock = self.sync_lock().lock().await;
+        self.save_changes(&changes, "import_state_snapshot").await?;
+
+        // `save_changes` only writes the sync token when there is one, so the one
+        // of this client must be removed explicitly for the snapshot to replace it.
+        if snapshot.sync_token.is_none() {
+            self.store.remove_kv_data(StateStoreDataKey::SyncToken).await?;
+        }
+
+        *self.store.sync_token.write().await = snapshot.sync_token;
+
+        for (room_id, room_info) in &changes.room_infos {
+            self.store.get_or_create_room(
+                room_id,
+                room_info.state(),
+                self.roominfo_update_sender.clone(),
+            );
+        }
+
+        self.apply_changes(&changes, false);
+
+        Ok(())
+    }
+
+    /// Get access to the store's sync lock.
+    pub fn sync_lock(&self) -> &Mutex<()> {
+        self.store.sync_lock()
+    }
+
+    /// Receive a response from a sync call.
+    ///
+    /// Once the response has been processed and the sync lock is acquired, its
+    /// changes are saved in the store and applied in memory by a separate
+    /// task. If the returned future is dropped before that point, nothing is
+    /// saved or applied and the response can be received again. If it is
+    /// dropped after that point, the task keeps running until the changes are
+    /// saved and applied, so the store and the in-memory state stay
+    /// consistent.
+    ///
+    /// # Arguments
+    ///
+    /// * `response` - The response that we received after a successful sync.
+    #[instrument(skip_all)]
+    pub async fn receive_sync_response(
+        &self,
+        response: api::sync::sync_events::v3::Response,
+    ) -> Result<SyncResponse> {
+        // The server might respond multiple times with the same sync token, in
+        // that case we already received this response and there's nothing to
+        // do.
+        let previous_sync_token = self.store.sync_token.read().await.clone();
+        if previous_sync_token.as_ref() == Some(&response.next_batch) {
+            info!("Got the same sync response twice");
+            return Ok(SyncResponse::default());
+        }
+
+        let now = Instant::now();
+        let mut changes = Box::new(StateChanges::new(response.next_batch.clone()));
+
+        #[cfg(feature = "e2e-encryption")]
+        let to_device = self
+            .preprocess_to_device_events(
+                EncryptionSyncChanges {
+                    to_device_events: response.to_device.events,
+                    changed_devices: &response.device_lists,
+                    one_time_keys_counts: &response.device_one_time_keys_count,
+                    unused_fallback_keys: response.device_unused_fallback_key_types.as_deref(),
+                    next_batch_token: Some(response.next_batch.clone()),
+                },
+                &mut changes,
             )
             .await?;
 
@@ -848,13 +1609,17 @@ impl BaseClient {
 
         self.handle_account_data(&response.account_data.events, &mut changes).await;
 
+        let push_rules_changed = self.push_rules_changed(&changes).await?;
         let push_rules = self.get_push_rules(&changes).await?;
 
         let mut new_rooms = RoomUpdates::default();
         let mut notifications = Default::default();
 
+        #[cfg(feature = "e2e-encryption")]
+        let mut decryption_time = Duration::ZERO;
+
         for (room_id, new_info) in response.rooms.join {
-            let room = self.store.get_or_create_room(
+            let room = self.store.get_or_new_room(
                 &room_id,
                 RoomState::Joined,
                 self.roominfo_update_sender.clone(),
@@ -886,6 +1651,9 @@ impl BaseClient {
                     Ok(AnySyncEphemeralRoomEvent::Receipt(event)) => {
                         changes.add_receipts(&room_id, event.content);
                     }
+                    Ok(AnySyncEphemeralRoomEvent::Typing(event)) => {
+                        changes.add_typing(&room_id, event.content.user_ids);
+                    }
                     Ok(_) => {}
                     Err(e) => {
                         let event_id: Option<String> = raw.get_field("event_id").ok().flatten();
@@ -914,6 +1682,8 @@ impl BaseClient {
                     &mut changes,
                     &mut notifications,
                     &mut ambiguity_cache,
+                    #[cfg(feature = "e2e-encryption")]
+                    &mut decryption_time,
                 )
                 .await?;
 
@@ -967,7 +1737,7 @@ impl BaseClient {
         }
 
         for (room_id, new_info) in response.rooms.leave {
-            let room = self.store.get_or_create_room(
+            let room = self.store.get_or_new_room(
                 &room_id,
                 RoomState::Left,
                 self.roominfo_update_sender.clone(),
@@ -975,7 +1745,11 @@ impl BaseClient {
 
             let mut room_info = room.clone_info();
             room_info.mark_as_left();
-            room_info.mark_state_partially_synced();
+            if !self.retain_left_rooms_state {
+                room_info.mark_state_partially_synced();
+                room_info.mark_members_missing(); // the own member event
+                                                  // changed
+            }
 
             let state_events = Self::deserialize_state_events(&new_info.state.events);
             let (raw_state_events, state_events): (Vec<_>, Vec<_>) =
@@ -1003,6 +1777,8 @@ impl BaseClient {
                     &mut changes,
                     &mut notifications,
                     &mut ambiguity_cache,
+                    #[cfg(feature = "e2e-encryption")]
+                    &mut decryption_time,
                 )
                 .await?;
 
@@ -1026,7 +1802,7 @@ impl BaseClient {
         }
 
         for (room_id, new_info) in response.rooms.invite {
-            let room = self.store.get_or_create_room(
+            let room = self.store.get_or_new_room(
                 &room_id,
                 RoomState::Invited,
                 self.roominfo_update_sender.clone(),
@@ -1069,12 +1845,41 @@ impl BaseClient {
 
         changes.ambiguity_maps = ambiguity_cache.cache;
 
-        {
-            let _sync_lock = self.sync_lock().lock().await;
-            self.store.save_changes(&changes).await?;
-            *self.store.sync_token.write().await = Some(response.next_batch.clone());
-            self.apply_changes(&changes, false);
-        }
+        self.handle_power_levels_changes(&mut changes).await;
+
+        // Acquire the sync lock before spawning the task, so that a future dropped
+        // while waiting for it leaves no trace. Once it's acquired, the changes are
+        // saved and applied in memory in a separate task, so that both steps run to
+        // completion even if this future is dropped while the changes are being
+        // written to the store.
+        let sync_lock = self.store.lock_sync_owned().await;
+        let save_and_apply = run_detached({
+            let client = self.clone();
+            let next_batch = response.next_batch.clone();
+
+            async move {
+                let _sync_lock = sync_lock;
+                client.save_changes(&changes, "receive_sync_response").await?;
+                *client.store.sync_token.write().await = Some(next_batch);
+
+                // The rooms that are new in this sync were only created with
+                // `Store::get_or_new_room`, add them to the store now that their
+                // changes are saved.
+                for (room_id, room_info) in &changes.room_infos {
+                    client.store.get_or_create_room(
+                        room_id,
+                        room_info.state(),
+                        client.roominfo_update_sender.clone(),
+                    );
+                }
+
+                client.apply_changes(&changes, false);
+
+                Ok::<_, Error>(())
+            }
+        });
+
+        save_and_apply.await??;
 
         // Now that all the rooms information have been saved, update the display name
         // cache (which relies on information stored in the database). This will
@@ -1083,19 +1888,112 @@ impl BaseClient {
         // above. Oh well.
         new_rooms.update_in_memory_caches(&self.store).await;
 
+        #[cfg(feature = "e2e-encryption")]
+        info!(?decryption_time, "Processed a sync response in {:?}", now.elapsed());
+        #[cfg(not(feature = "e2e-encryption"))]
         info!("Processed a sync response in {:?}", now.elapsed());
 
+        if self.suppress_initial_sync_notifications && previous_sync_token.is_none() {
+            notifications.clear();
+        }
+
         let response = SyncResponse {
             rooms: new_rooms,
             presence: response.presence.events,
             account_data: response.account_data.events,
             to_device,
             notifications,
+            push_rules_changed,
         };
 
         Ok(response)
     }
 
+    /// Receive a sequence of previously stored responses from sync calls.
+    ///
+    /// The responses are applied in order, as if they were received one after
+    /// the other by [`BaseClient::receive_sync_response`]. Responses with a
+    /// `next_batch` token that was already seen are skipped.
+    ///
+    /// Returns a single [`SyncResponse`] merging all the updates.
+    ///
+    /// # Arguments
+    ///
+    /// * `responses` - The responses to replay, from the oldest to the newest.
+    pub async fn replay_sync_responses(
+        &self,
+        responses: Vec<api::sync::sync_events::v3::Response>,
+    ) -> Result<SyncResponse> {
+        let mut seen_tokens = BTreeSet::new();
+        let mut merged = SyncResponse::default();
+
+        for response in responses {
+            if !seen_tokens.insert(response.next_batch.clone()) {
+                debug!(next_batch = ?response.next_batch, "Skipping a duplicate sync response");
+                continue;
+            }
+
+            merged.extend(self.receive_sync_response(response).await?);
+        }
+
+        Ok(merged)
+    }
+
+    /// Save the changes in the state store.
+    ///
+    /// If it fails, the error is also sent to the subscribers of
+    /// [`BaseClient::subscribe_to_store_errors`], with the name of the
+    /// `operation` that was running.
+    pub(crate) async fn save_changes(
+        &self,
+        changes: &StateChanges,
+        operation: &'static str,
+    ) -> StoreResult<()> {
+        let result = self.store.save_changes(changes).await;
+
+        if let Err(error) = &result {
+            self.report_store_error(operation, error);
+        }
+
+        result
+    }
+
+    /// Record in the changes the new power levels of the known rooms whose
+    /// `m.room.power_levels` event has a different content than the previous
+    /// one.
+    pub(crate) async fn handle_power_levels_changes(&self, changes: &mut StateChanges) {
+        let mut power_levels = BTreeMap::new();
+
+        for room_id in changes.state.keys() {
+            let Some(room) = self.store.room(room_id) else {
+                continue;
+            };
+            let Some((content, event)) = power_levels_event(changes, room_id) else {
+                continue;
+            };
+
+            if room.is_power_levels_content_changed(&content).await {
+                power_levels.insert(room_id.clone(), event.power_levels());
+            }
+        }
+
+        changes.power_levels = power_levels;
+    }
+
+    fn report_store_error(&self, operation: &'static str, error: &StoreError) {
+        warn!(operation, "Failed to save changes in the state store: {error}");
+
+        // Ignore error if no receiver exists.
+        let _ = self
+            .store_errors_sender
+            .send(StoreErrorEvent { operation, message: error.to_string() });
+    }
+
+    /// Apply the changes to the in-memory state and notify the observers.
+    ///
+    /// This must only be called once the changes have been saved successfully
+    /// in the store, so that observers never see changes that were not
+    /// committed.
     pub(crate) fn apply_changes(&self, changes: &StateChanges, trigger_room_list_update: bool) {
         if changes.account_data.contains_key(&GlobalAccountDataEventType::IgnoredUserList) {
             if let Some(event) =
@@ -1106,6 +2004,18 @@ impl BaseClient {
                         let user_ids: Vec<String> =
                             event.content.ignored_users.keys().map(|id| id.to_string()).collect();
 
+                        let previous_user_ids = self.ignore_user_list_changes.get();
+                        for user_id in user_ids
+                            .iter()
+                            .filter(|id| !previous_user_ids.contains(id))
+                            .chain(previous_user_ids.iter().filter(|id| !user_ids.contains(id)))
+                        {
+                            if let Ok(user_id) = OwnedUserId::try_from(user_id.as_str()) {
+                                // Ignore error if no receiver exists.
+                                let _ = self.ignored_user_effects_sender.send(user_id);
+                            }
+                        }
+
                         self.ignore_user_list_changes.set(user_ids);
                     }
                     Err(error) => {
@@ -1115,9 +2025,115 @@ impl BaseClient {
             }
         }
 
+        // Collect the new tombstones before the room infos are applied, so that a
+        // tombstone the room already had, e.g. because it was delivered again,
+        // isn't reported as a new room upgrade.
+        let room_upgrades: Vec<_> = changes
+            .state
+            .iter()
+            .filter_map(|(room_id, state)| {
+                let event = state.get(&StateEventType::RoomTombstone)?.get("")?;
+                let SyncStateEvent::Original(tombstone) =
+                    event.deserialize_as::<SyncRoomTombstoneEvent>().ok()?
+                else {
+                    return None;
+                };
+
+                let previous_tombstone =
+                    self.store.room(room_id).and_then(|room| room.clone_info().base_info.tombstone);
+                let is_known = matches!(
+                    previous_tombstone,
+                    Some(MinimalStateEvent::Original(previous))
+                        if previous.event_id.as_ref() == Some(&tombstone.event_id)
+                );
+
+                (!is_known).then(|| (room_id.clone(), tombstone.content.replacement_room))
+            })
+            .collect();
+
+        let mut unread_counts_changed = false;
+
         for (room_id, room_info) in &changes.room_infos {
-            if let Some(room) = self.store.room(room_id) {
-                room.set_room_info(room_info.clone(), trigger_room_list_update)
+            let Some(room) = self.store.room(room_id) else {
+                continue;
+            };
+
+            // Don't notify about a room info that didn't change, which is common
+            // when a sync touches a room without meaningful updates.
+            if room.has_same_info(room_info) {
+                continue;
+            }
+
+            if room_info.is_encrypted() && !room.is_encrypted() {
+                // Ignore error if no receiver exists.
+                let _ = self.room_encryption_enabled_sender.send(room_id.clone());
+            }
+
+            unread_counts_changed |=
+                room.unread_notification_counts() != room_info.notification_counts;
+
+            room.set_room_info(room_info.clone(), trigger_room_list_update)
+        }
+
+        // Only sum the counts of all the rooms again when some of them changed.
+        if unread_counts_changed {
+            self.total_unread_counts.set_if_not_eq(self.total_unread_counts());
+        }
+
+        if self.state_changes_sender.receiver_count() > 0 {
+            // Ignore error if no receiver exists.
+            let _ = self.state_changes_sender.send(changes.summary());
+        }
+
+        for room_upgrade in room_upgrades {
+            // Ignore error if no receiver exists.
+            let _ = self.room_upgrades_sender.send(room_upgrade);
+        }
+
+        for (room_id, power_levels) in &changes.power_levels {
+            let Some(room) = self.store.room(room_id) else {
+                continue;
+            };
+            let Some((content, _)) = power_levels_event(changes, room_id) else {
+                continue;
+            };
+
+            room.set_power_levels(content, power_levels.clone());
+        }
+
+        for (room_id, event) in &changes.timeline_events {
+            for handler in self.handlers_for_timeline_event(event) {
+                handler(room_id, event);
+            }
+        }
+
+        #[cfg(feature = "e2e-encryption")]
+        for user_id in &changes.changed_devices {
+            // Ignore error if no receiver exists.
+            let _ = self.device_list_changes_sender.send(user_id.clone());
+        }
+
+        for (room_id, user_ids) in &changes.typing {
+            // Ignore error if no receiver exists.
+            let _ = self.typing_sender.send((room_id.clone(), user_ids.clone()));
+        }
+
+        if self.receipts_sender.receiver_count() > 0 {
+            for (room_id, content) in &changes.receipts {
+                for (event_id, receipts) in content.iter() {
+                    for (receipt_type, users) in receipts {
+                        for (user_id, receipt) in users {
+                            // Ignore error if no receiver exists.
+                            let _ = self.receipts_sender.send(ReceiptUpdate {
+                                room_id: room_id.clone(),
+                                user_id: user_id.clone(),
+                                event_id: event_id.clone(),
+                                receipt_type: receipt_type.clone(),
+                                thread: receipt.thread.clone(),
+                            });
+                        }
+                    }
+                }
             }
         }
     }
@@ -1221,12 +2237,68 @@ impl BaseClient {
         room_info.mark_members_synced();
         changes.add_room(room_info);
 
-        self.store.save_changes(&changes).await?;
+        self.save_changes(&changes, "receive_all_members").await?;
         self.apply_changes(&changes, false);
 
         Ok(())
     }
 
+    /// Receive a get member events response that was requested at a given
+    /// point in time, with the `at` parameter.
+    ///
+    /// The members are stored as a snapshot for the `at` token, that can be
+    /// retrieved with [`Room::members_at`]. This doesn't affect the live member
+    /// list of the room, nor the store.
+    ///
+    /// This client-server request must be made with the `at` parameter and
+    /// without filters to make sure all members are received. Otherwise, an
+    /// error is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The room id this response belongs to.
+    ///
+    /// * `request` - The request that was sent to the server.
+    ///
+    /// * `response` - The raw response that was received from the server.
+    #[instrument(skip_all, fields(?room_id))]
+    pub async fn receive_members_at(
+        &self,
+        room_id: &RoomId,
+        request: &api::membership::get_member_events::v3::Request,
+        response: &api::membership::get_member_events::v3::Response,
+    ) -> Result<()> {
+        let Some(at) = &request.at else {
+            return Err(Error::InvalidReceiveMembersParameters);
+        };
+
+        if request.membership.is_some() || request.not_membership.is_some() {
+            return Err(Error::InvalidReceiveMembersParameters);
+        }
+
+        let Some(room) = self.store.room(room_id) else {
+            // The room is unknown to us: leave early.
+            return Ok(());
+        };
+
+        let members = response
+            .chunk
+            .iter()
+            .filter_map(|raw_event| match raw_event.deserialize() {
+                Ok(ev) => Some(ev),
+                Err(e) => {
+                    let event_id: Option<String> = raw_event.get_field("event_id").ok().flatten();
+                    debug!(event_id, "Failed to deserialize member event: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        room.set_members_at(at.clone(), members);
+
+        Ok(())
+    }
+
     /// Receive a successful filter upload response, the filter id will be
     /// stored under the given name in the store.
     ///
@@ -1277,19 +2349,57 @@ impl BaseClient {
         Ok(filter)
     }
 
+    /// Get the data that the client stored under the given key with
+    /// [`BaseClient::set_custom_data`].
+    pub async fn get_custom_data(&self, key: &str) -> StoreResult<Option<Vec<u8>>> {
+        self.store.get_custom_value(&custom_data_key(key)).await
+    }
+
+    /// Store arbitrary data for the client, like UI state, alongside the
+    /// state of the SDK.
+    ///
+    /// The keys are namespaced, so they can't collide with the ones used by
+    /// the SDK.
+    pub async fn set_custom_data(&self, key: &str, value: Vec<u8>) -> StoreResult<()> {
+        self.store.set_custom_value_no_read(&custom_data_key(key), value).await
+    }
+
+    /// Remove the data that the client stored under the given key with
+    /// [`BaseClient::set_custom_data`].
+    pub async fn remove_custom_data(&self, key: &str) -> StoreResult<()> {
+        self.store.remove_custom_value(&custom_data_key(key)).await?;
+        Ok(())
+    }
+
+    /// Get the settings that would be used to encrypt the room keys of the
+    /// room with the given id.
+    ///
+    /// This takes the encryption state event, the history visibility and the
+    /// room key recipient strategy of the room into account.
+    ///
+    /// Returns `None` if the room is unknown or isn't encrypted.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn room_encryption_settings(&self, room_id: &RoomId) -> Option<EncryptionSettings> {
+        let room = self.get_room(room_id)?;
+        let settings = room.encryption_settings()?;
+
+        Some(EncryptionSettings {
+            sharing_strategy: self.room_key_recipient_strategy_for_room(room_id),
+            ..EncryptionSettings::new(settings, room.history_visibility(), false)
+        })
+    }
+
     /// Get a to-device request that will share a room key with users in a room.
     #[cfg(feature = "e2e-encryption")]
     pub async fn share_room_key(&self, room_id: &RoomId) -> Result<Vec<Arc<ToDeviceRequest>>> {
         match self.olm_machine().await.as_ref() {
             Some(o) => {
-                let (history_visibility, settings) = self
-                    .get_room(room_id)
-                    .map(|r| (r.history_visibility(), r.encryption_settings()))
-                    .unwrap_or((HistoryVisibility::Joined, None));
+                let settings =
+                    self.room_encryption_settings(room_id).ok_or(Error::EncryptionNotEnabled)?;
 
                 // Don't share the group session with members that are invited
                 // if the history visibility is set to `Joined`
-                let filter = if history_visibility == HistoryVisibility::Joined {
+                let filter = if settings.history_visibility == HistoryVisibility::Joined {
                     RoomMemberships::JOIN
                 } else {
                     RoomMemberships::ACTIVE
@@ -1297,9 +2407,6 @@ impl BaseClient {
 
                 let members = self.store.get_user_ids(room_id, filter).await?;
 
-                let settings = settings.ok_or(Error::EncryptionNotEnabled)?;
-                let settings = EncryptionSettings::new(settings, history_visibility, false);
-
                 Ok(o.share_room_key(room_id, members.iter().map(Deref::deref), settings).await?)
             }
             None => panic!("Olm machine wasn't started"),
@@ -1315,20 +2422,241 @@ impl BaseClient {
         self.store.room(room_id)
     }
 
-    /// Get the olm machine.
-    #[cfg(feature = "e2e-encryption")]
-    pub async fn olm_machine(&self) -> RwLockReadGuard<'_, Option<OlmMachine>> {
-        self.olm_machine.read().await
+    /// Get the source of the avatar to render for the room with the given id.
+    ///
+    /// Returns `None` if the room is unknown. See [`Room::avatar_source`].
+    pub fn room_avatar_source(&self, room_id: &RoomId) -> Option<RoomAvatarSource> {
+        self.store.room(room_id).map(|room| room.avatar_source())
     }
 
-    /// Get the push rules.
+    /// Get the URL to use to access the given media.
     ///
-    /// Gets the push rules from `changes` if they have been updated, otherwise
-    /// get them from the store. As a fallback, uses
-    /// `Ruleset::server_default` if the user is logged in.
-    pub async fn get_push_rules(&self, changes: &StateChanges) -> Result<Ruleset> {
-        if let Some(event) = changes
-            .account_data
+    /// This is the URL returned by the function set with
+    /// [`BaseClient::with_media_url_rewriter`], or `None` if no function was
+    /// set, in which case the media should be accessed through its `mxc://`
+    /// URL as usual.
+    pub fn rewrite_media_url(&self, url: &MxcUri) -> Option<String> {
+        self.media_url_rewriter.as_ref().map(|rewriter| rewriter(url))
+    }
+
+    /// Get the rooms with the given ids, e.g. to render the children of a
+    /// space.
+    ///
+    /// The returned list has the same order as `room_ids`, with `None` for the
+    /// rooms that are unknown.
+    pub fn get_rooms(&self, room_ids: &[OwnedRoomId]) -> Vec<Option<Room>> {
+        self.store.rooms_by_id(room_ids)
+    }
+
+    /// Get a consolidated view of the information needed to render the room
+    /// with the given id in a room list.
+    ///
+    /// Returns `None` if the room is unknown. See [`Room::summary_view`].
+    pub fn room_summary(&self, room_id: &RoomId) -> Option<RoomSummaryView> {
+        self.store.room(room_id).map(|room| room.summary_view())
+    }
+
+    /// Check whether our own user is allowed to redact the given event, in the
+    /// room with the given id.
+    ///
+    /// This compares the power level of our own user with the level required
+    /// to redact events, which depends on whether we sent the event or not.
+    ///
+    /// Returns `false` if the client isn't logged in or the room is unknown.
+    pub async fn can_redact_event(
+        &self,
+        room_id: &RoomId,
+        event: &SyncTimelineEvent,
+    ) -> Result<bool> {
+        let Some(own_user_id) = self.user_id() else {
+            return Ok(false);
+        };
+
+        if self.store.room(room_id).is_none() {
+            return Ok(false);
+        }
+
+        let power_levels = self
+            .store
+            .get_state_event_static::<RoomPowerLevelsEventContent>(room_id)
+            .await?
+            .and_then(|event| event.deserialize().ok())
+            .map(|event| event.power_levels())
+            .unwrap_or_else(|| RoomPowerLevels::from(RoomPowerLevelsEventContent::new()));
+
+        let sender = event.event.get_field::<OwnedUserId>("sender").ok().flatten();
+
+        Ok(if sender.as_deref() == Some(own_user_id) {
+            power_levels.user_can_redact_own_event(own_user_id)
+        } else {
+            power_levels.user_can_redact_event_of_other(own_user_id)
+        })
+    }
+
+    /// Check whether the room with the given id is encrypted.
+    ///
+    /// This is cheaper than getting the room with [`BaseClient::get_room`]
+    /// first.
+    ///
+    /// Returns `None` if the room is unknown.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room to check.
+    pub fn is_room_encrypted(&self, room_id: &RoomId) -> Option<bool> {
+        self.store.is_room_encrypted(room_id)
+    }
+
+    /// Get the profile that the given user set in the given room, as stored
+    /// from their last `m.room.member` event.
+    ///
+    /// This doesn't require to load the whole member, which is useful to
+    /// render the sender of a message.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room.
+    ///
+    /// * `user_id` - The id of the user.
+    pub async fn stored_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> StoreResult<Option<MinimalRoomMemberEvent>> {
+        self.store.get_profile(room_id, user_id).await
+    }
+
+    /// Check whether the display name of the given user is ambiguous in the
+    /// given room, i.e. whether at least one other member shares it.
+    ///
+    /// Clients usually show the user ID next to ambiguous display names.
+    ///
+    /// Returns `false` if the user is not a member of the room.
+    ///
+    /// # Arguments
+    ///
+    /// * `room_id` - The id of the room.
+    ///
+    /// * `user_id` - The id of the user.
+    pub async fn is_display_name_ambiguous(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> StoreResult<bool> {
+        let Some(event) = self.store.get_member_event(room_id, user_id).await? else {
+            return Ok(false);
+        };
+        let Ok(event) = event.deserialize() else {
+            return Ok(false);
+        };
+
+        let users = self.store.get_users_with_display_name(room_id, event.display_name()).await?;
+
+        Ok(users.len() > 1)
+    }
+
+    /// Scan the rooms of the store for inconsistencies.
+    ///
+    /// This checks that the `m.room.member` event of our own user exists in
+    /// every room, and that it matches the state of the room. This is useful
+    /// to debug reports of a corrupted store.
+    pub async fn validate_store(&self) -> Result<StoreValidationReport> {
+        let mut report = StoreValidationReport::default();
+
+        for room in self.store.rooms() {
+            let room_id = room.room_id().to_owned();
+            let room_state = room.state();
+
+            let Some(event) = self.store.get_member_event(&room_id, room.own_user_id()).await?
+            else {
+                report.issues.push(StoreValidationIssue::MissingOwnMember { room_id });
+                continue;
+            };
+
+            let membership = match event.deserialize() {
+                Ok(event) => event.membership().clone(),
+                Err(error) => {
+                    warn!(?room_id, "Failed to deserialize own member event: {error}");
+                    report.issues.push(StoreValidationIssue::MissingOwnMember { room_id });
+                    continue;
+                }
+            };
+
+            let is_consistent = match room_state {
+                RoomState::Joined => membership == MembershipState::Join,
+                RoomState::Invited => membership == MembershipState::Invite,
+                RoomState::Left => {
+                    matches!(membership, MembershipState::Leave | MembershipState::Ban)
+                }
+            };
+
+            if !is_consistent {
+                report.issues.push(StoreValidationIssue::OwnMembershipMismatch {
+                    room_id,
+                    room_state,
+                    membership,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Get the ID of a known room advertising the given alias.
+    ///
+    /// This only looks at the canonical and alternative aliases of the rooms
+    /// this client knows about, without any network request. Returns `None` if
+    /// no known room advertises the alias, in which case it needs to be
+    /// resolved by the homeserver.
+    ///
+    /// # Arguments
+    ///
+    /// * `alias` - The alias of the room that should be looked up.
+    pub fn room_id_for_alias(&self, alias: &RoomAliasId) -> Option<OwnedRoomId> {
+        self.store.rooms().into_iter().find_map(|room| {
+            let matches = room.canonical_alias().as_deref() == Some(alias)
+                || room.alt_aliases().iter().any(|alt_alias| alt_alias == alias);
+            matches.then(|| room.room_id().to_owned())
+        })
+    }
+
+    /// Get the olm machine.
+    ///
+    /// If the session meta is set but the olm machine is missing, this tries
+    /// to create it on demand, unless encryption was disabled because it
+    /// couldn't be created in [`BaseClient::set_session_meta`]. If creating it
+    /// fails, it is tried again on the next call.
+    #[cfg(feature = "e2e-encryption")]
+    pub async fn olm_machine(&self) -> RwLockReadGuard<'_, Option<OlmMachine>> {
+        let olm_machine = self.olm_machine.read().await;
+
+        if olm_machine.is_some() || self.session_meta().is_none() || self.is_encryption_disabled() {
+            return olm_machine;
+        }
+
+        drop(olm_machine);
+
+        // Only one caller creates the machine, the others wait for it with the lock.
+        let mut olm_machine = self.olm_machine.write().await;
+
+        if olm_machine.is_none() && !self.is_encryption_disabled() {
+            debug!("The OlmMachine is missing, creating it on demand");
+            if let Err(error) = self.regenerate_olm_locked(&mut olm_machine, None).await {
+                warn!("Couldn't create the OlmMachine on demand: {error}");
+            }
+        }
+
+        olm_machine.downgrade()
+    }
+
+    /// Get the push rules.
+    ///
+    /// Gets the push rules from `changes` if they have been updated, otherwise
+    /// get them from the store. As a fallback, uses
+    /// `Ruleset::server_default` if the user is logged in.
+    pub async fn get_push_rules(&self, changes: &StateChanges) -> Result<Ruleset> {
+        if let Some(event) = changes
+            .account_data
             .get(&GlobalAccountDataEventType::PushRules)
             .and_then(|ev| ev.deserialize_as::<PushRulesEvent>().ok())
         {
@@ -1347,6 +2675,26 @@ impl BaseClient {
         }
     }
 
+    /// Whether the push rules in `changes` differ from the ones in the store.
+    pub(crate) async fn push_rules_changed(&self, changes: &StateChanges) -> Result<bool> {
+        let Some(new_event) = changes.account_data.get(&GlobalAccountDataEventType::PushRules)
+        else {
+            return Ok(false);
+        };
+
+        let Some(previous_event) =
+            self.store.get_account_data_event(GlobalAccountDataEventType::PushRules).await?
+        else {
+            return Ok(true);
+        };
+
+        let content = |event: &Raw<AnyGlobalAccountDataEvent>| {
+            event.get_field::<serde_json::Value>("content").ok().flatten()
+        };
+
+        Ok(content(&previous_event) != content(new_event))
+    }
+
     /// Get the push context for the given room.
     ///
     /// Tries to get the data from `changes` or the up to date `room_info`.
@@ -1455,12 +2803,78 @@ impl BaseClient {
         }
     }
 
+    /// Get the sum of the unread notification counts of all the rooms.
+    pub fn total_unread_counts(&self) -> UnreadNotificationsCount {
+        self.store.rooms().iter().map(Room::unread_notification_counts).fold(
+            UnreadNotificationsCount::default(),
+            |total, counts| UnreadNotificationsCount {
+                highlight_count: total.highlight_count + counts.highlight_count,
+                notification_count: total.notification_count + counts.notification_count,
+            },
+        )
+    }
+
+    /// Get the rooms with unread highlights, e.g. mentions, along with their
+    /// highlight count.
+    pub fn rooms_with_highlights(&self) -> Vec<(OwnedRoomId, u64)> {
+        self.store
+            .rooms()
+            .iter()
+            .filter_map(|room| {
+                let highlight_count = room.unread_notification_counts().highlight_count;
+                (highlight_count > 0).then(|| (room.room_id().to_owned(), highlight_count))
+            })
+            .collect()
+    }
+
+    /// Returns a subscriber that publishes the sum of the unread notification
+    /// counts of all the rooms every time it changes.
+    pub fn subscribe_to_total_unread_counts(&self) -> Subscriber<UnreadNotificationsCount> {
+        self.total_unread_counts.subscribe()
+    }
+
+    /// Check whether the given user is in the ignored user list.
+    ///
+    /// This doesn't access the store, it uses the latest ignored user list
+    /// that was received, so it is cheap enough to be called for every event
+    /// of a timeline.
+    pub fn is_user_ignored(&self, user_id: &UserId) -> bool {
+        self.ignore_user_list_changes.read().iter().any(|id| id == user_id.as_str())
+    }
+
     /// Returns a subscriber that publishes an event every time the ignore user
     /// list changes
     pub fn subscribe_to_ignore_user_list_changes(&self) -> Subscriber<Vec<String>> {
         self.ignore_user_list_changes.subscribe()
     }
 
+    /// Returns a new receiver that gets the ID of a room and of its successor
+    /// every time an `m.room.tombstone` event is received for the room.
+    ///
+    /// This allows to prompt the user to move to the new room.
+    pub fn subscribe_to_room_upgrades(&self) -> broadcast::Receiver<(OwnedRoomId, OwnedRoomId)> {
+        self.room_upgrades_sender.subscribe()
+    }
+
+    /// Returns a new receiver that gets the ID of a user every time they are
+    /// added to or removed from the ignored user list.
+    ///
+    /// This allows to re-render the timelines to hide or show the events of
+    /// this user.
+    pub fn subscribe_to_ignored_user_effects(&self) -> broadcast::Receiver<OwnedUserId> {
+        self.ignored_user_effects_sender.subscribe()
+    }
+
+    /// Returns a new receiver that gets an event every time the client fails
+    /// to save changes in the state store.
+    ///
+    /// The error is still returned by the failing operation, e.g.
+    /// [`BaseClient::receive_sync_response`], this allows to monitor the
+    /// transient failures of the store in a single place.
+    pub fn subscribe_to_store_errors(&self) -> broadcast::Receiver<StoreErrorEvent> {
+        self.store_errors_sender.subscribe()
+    }
+
     pub(crate) fn deserialize_state_events(
         raw_events: &[Raw<AnySyncStateEvent>],
     ) -> Vec<(Raw<AnySyncStateEvent>, AnySyncStateEvent)> {
@@ -1484,6 +2898,71 @@ impl BaseClient {
     pub fn roominfo_update_receiver(&self) -> broadcast::Receiver<RoomInfoUpdate> {
         self.roominfo_update_sender.subscribe()
     }
+
+    /// Returns a new receiver that gets the ID of a user every time the list of
+    /// devices of this user changed, as announced in a sync response whose
+    /// changes have been saved in the store.
+    ///
+    /// This is useful to prompt the user to verify new devices.
+    #[cfg(feature = "e2e-encryption")]
+    pub fn subscribe_to_device_list_changes(&self) -> broadcast::Receiver<OwnedUserId> {
+        self.device_list_changes_sender.subscribe()
+    }
+
+    /// Register a handler that is called for every timeline event of the given
+    /// type received during sync.
+    ///
+    /// This allows processing custom events without having to deserialize them
+    /// again from the [`SyncResponse`]. Several handlers can be registered for
+    /// the same event type, they are called in the order they were registered.
+    ///
+    /// The handlers are only called once the changes of the sync have been
+    /// saved in the store, so they aren't called for a sync that failed.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_type` - The `type` of the events the handler should be called
+    ///   for, e.g. `org.example.custom`.
+    ///
+    /// * `handler` - The handler, called with the room ID and the raw event.
+    pub fn register_timeline_event_handler<F>(&self, event_type: impl Into<String>, handler: F)
+    where
+        F: Fn(&RoomId, &Raw<AnySyncTimelineEvent>) + Send + Sync + 'static,
+    {
+        self.timeline_event_handlers
+            .write()
+            .unwrap()
+            .entry(event_type.into())
+            .or_default()
+            .push(Arc::new(handler));
+    }
+
+    /// Returns a new receiver that gets a summary of the changes every time
+    /// they have been saved in the store.
+    ///
+    /// This is useful for external indexers, to know what needs to be
+    /// re-indexed.
+    pub fn add_state_changes_subscriber(&self) -> broadcast::Receiver<StateChangesSummary> {
+        self.state_changes_sender.subscribe()
+    }
+
+    /// Returns a new receiver that gets the list of users that are currently
+    /// typing in a room, every time a typing notification is received.
+    pub fn subscribe_to_typing(&self) -> broadcast::Receiver<(OwnedRoomId, Vec<OwnedUserId>)> {
+        self.typing_sender.subscribe()
+    }
+
+    /// Returns a new receiver that gets the read receipts received during
+    /// sync, once they have been saved in the store.
+    pub fn subscribe_to_receipts(&self) -> broadcast::Receiver<ReceiptUpdate> {
+        self.receipts_sender.subscribe()
+    }
+
+    /// Returns a new receiver that gets the ID of a room every time the room
+    /// turns on encryption.
+    pub fn subscribe_to_room_encryption_enabled(&self) -> broadcast::Receiver<OwnedRoomId> {
+        self.room_encryption_enabled_sender.subscribe()
+    }
 }
 
 impl Default for BaseClient {
@@ -1492,6 +2971,91 @@ impl Default for BaseClient {
     }
 }
 
+/// Whether the given state event is already in the state changes of the room.
+fn is_state_event_in_changes(
+    changes: &StateChanges,
+    room_id: &RoomId,
+    event: &AnySyncStateEvent,
+) -> bool {
+    changes
+        .state
+        .get(room_id)
+        .and_then(|events| events.get(&event.event_type())?.get(event.state_key()))
+        .and_then(|raw| raw.get_field::<OwnedEventId>("event_id").ok().flatten())
+        .is_some_and(|event_id| event_id == event.event_id())
+}
+
+/// The prefix of the keys of the custom data set by the client.
+const CUSTOM_DATA_KEY_PREFIX: &str = "client_custom_data:";
+
+/// Get the content and the deserialized `m.room.power_levels` event of the
+/// given room in the changes, if any.
+fn power_levels_event(
+    changes: &StateChanges,
+    room_id: &RoomId,
+) -> Option<(serde_json::Value, SyncRoomPowerLevelsEvent)> {
+    let raw_event = changes.state.get(room_id)?.get(&StateEventType::RoomPowerLevels)?.get("")?;
+    let content = raw_event.get_field("content").ok().flatten()?;
+    let event = raw_event.deserialize_as().ok()?;
+
+    Some((content, event))
+}
+
+/// Run the given future in a new task that keeps running even if the returned
+/// future is dropped, and wait for its output.
+///
+/// If the task panics, the panic is resumed in the caller.
+#[cfg(not(target_arch = "wasm32"))]
+async fn run_detached<F>(future: F) -> Result<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    // Dropping a `JoinHandle` detaches the task.
+    match spawn(future).await {
+        Ok(output) => Ok(output),
+        Err(error) if error.is_panic() => std::panic::resume_unwind(error.into_panic()),
+        Err(_) => Err(Error::TaskCancelled),
+    }
+}
+
+/// Run the given future in a new task that keeps running even if the returned
+/// future is dropped, and wait for its output.
+#[cfg(target_arch = "wasm32")]
+async fn run_detached<F>(future: F) -> Result<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    // Dropping a `JoinHandle` aborts the task on WebAssembly, so the task keeps
+    // its own handle until it's done.
+    let (join_handle_sender, join_handle_receiver) = oneshot::channel();
+    let (output_sender, output_receiver) = oneshot::channel();
+
+    let join_handle = spawn(async move {
+        let _join_handle = join_handle_receiver.await;
+        // Ignore error if the caller was dropped.
+        let _ = output_sender.send(future.await);
+    });
+    // Ignore error, the task can't be done before it gets its handle.
+    let _ = join_handle_sender.send(join_handle);
+
+    output_receiver.await.map_err(|_| Error::TaskCancelled)
+}
+
+fn custom_data_key(key: &str) -> Vec<u8> {
+    format!("{CUSTOM_DATA_KEY_PREFIX}{key}").into_bytes()
+}
+
+/// Whether the given account data event has an empty content.
+fn is_empty_account_data<T>(raw_event: &Raw<T>) -> bool {
+    raw_event
+        .get_field::<BTreeMap<String, serde_json::Value>>("content")
+        .ok()
+        .flatten()
+        .is_some_and(|content| content.is_empty())
+}
+
 fn handle_room_member_event_for_profiles(
     room_id: &RoomId,
     event: &SyncStateEvent<RoomMemberEventContent>,
@@ -1525,22 +3089,42 @@ fn handle_room_member_event_for_profiles(
 
 #[cfg(test)]
 mod tests {
+    use std::{
+        pin::pin,
+        sync::{atomic::Ordering, Arc, Mutex},
+    };
+
+    use futures_util::{FutureExt, StreamExt};
     use matrix_sdk_test::{
-        async_test, response_from_file, sync_timeline_event, InvitedRoomBuilder, LeftRoomBuilder,
-        StateTestEvent, StrippedStateTestEvent, SyncResponseBuilder,
+        async_test, response_from_file, sync_timeline_event, EphemeralTestEvent,
+        GlobalAccountDataTestEvent, InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder,
+        RoomAccountDataTestEvent, StateTestEvent, StrippedStateTestEvent, SyncResponseBuilder,
     };
     use ruma::{
         api::{client as api, IncomingResponse},
-        room_id,
+        event_id,
+        events::{
+            receipt::{ReceiptThread, ReceiptType},
+            tag::TagName,
+        },
+        room_alias_id, room_id,
         serde::Raw,
-        user_id, UserId,
+        user_id, OwnedEventId, UserId,
     };
     use serde_json::{json, value::to_raw_value};
 
     use super::BaseClient;
     use crate::{
-        store::StateStoreExt, test_utils::logged_in_base_client, DisplayName, RoomState,
-        SessionMeta,
+        deserialized_responses::SyncTimelineEvent,
+        store::{
+            MemoryStore, StateChanges, StateSnapshot, StateStoreDataKey, StateStoreExt, StoreConfig,
+        },
+        test_utils::{
+            logged_in_base_client, logged_in_base_client_with_store_config, receive_joined_room,
+            TestStateStore,
+        },
+        DisplayName, ReceiptUpdate, RoomInfo, RoomMemberships, RoomState, SessionMeta,
+        StoreValidationIssue,
     };
 
     #[async_test]
@@ -1587,6 +3171,81 @@ mod tests {
         assert_eq!(client.get_room(room_id).unwrap().state(), RoomState::Invited);
     }
 
+    #[async_test]
+    async fn test_join_after_invite() {
+        use ruma::events::room::member::MembershipState;
+
+        use crate::deserialized_responses::RawAnySyncOrStrippedState;
+
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+
+        let response = sync_builder
+            .add_invited_room(InvitedRoomBuilder::new(room_id).add_state_event(
+                StrippedStateTestEvent::Custom(json!({
+                    "content": {
+                        "displayname": "Alice",
+                        "membership": "invite",
+                    },
+                    "event_id": "$143273582443PhrSn:example.org",
+                    "origin_server_ts": 1432735824653u64,
+                    "sender": "@example:example.org",
+                    "state_key": user_id,
+                    "type": "m.room.member",
+                })),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.state(), RoomState::Invited);
+        assert_matches::assert_matches!(
+            client.store.get_member_event(room_id, user_id).await.unwrap(),
+            Some(RawAnySyncOrStrippedState::Stripped(_))
+        );
+
+        // The join event comes in the timeline of the next sync.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_timeline_event(sync_timeline_event!({
+                "content": {
+                    "displayname": "Alice",
+                    "membership": "join",
+                },
+                "event_id": "$994173582443PhrSn:example.org",
+                "origin_server_ts": 1432735824654u64,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            })),
+        )
+        .await;
+
+        // The room is joined, and the stripped state of the invite is gone.
+        assert_eq!(room.state(), RoomState::Joined);
+        assert_eq!(room.inviter(), None);
+        assert_eq!(room.own_membership(), MembershipState::Join);
+        assert_matches::assert_matches!(
+            client.store.get_member_event(room_id, user_id).await.unwrap(),
+            Some(RawAnySyncOrStrippedState::Sync(_))
+        );
+        assert_eq!(
+            client.store.get_user_ids(room_id, RoomMemberships::JOIN).await.unwrap(),
+            [user_id.to_owned()]
+        );
+        assert!(client
+            .store
+            .get_user_ids(room_id, RoomMemberships::INVITE)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
     #[async_test]
     async fn test_invite_displayname() {
         let user_id = user_id!("@alice:example.org");
@@ -1700,6 +3359,72 @@ mod tests {
         assert!(changes.room_infos.is_empty());
     }
 
+    #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
+    #[async_test]
+    async fn test_refresh_all_latest_events() {
+        use std::iter;
+
+        use matrix_sdk_crypto::{EncryptionSettings, OlmMachine};
+        use ruma::{device_id, events::AnyMessageLikeEventContent};
+
+        let user_id = user_id!("@u:u.to");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        // Given two rooms whose latest events were encrypted by someone who didn't
+        // share the room keys with us
+        let alice = OlmMachine::new(user_id!("@alice:u.to"), device_id!("ALICEDEVICE")).await;
+        let room_ids = [room_id!("!r1:u.to"), room_id!("!r2:u.to")];
+        let content: Raw<AnyMessageLikeEventContent> =
+            Raw::new(&json!({ "body": "Hello", "msgtype": "m.text" })).unwrap().cast();
+
+        for (i, room_id) in room_ids.into_iter().enumerate() {
+            let room = process_room_join_test_helper(&client, room_id, "$1", user_id).await;
+
+            alice
+                .share_room_key(room_id, iter::empty(), EncryptionSettings::default())
+                .await
+                .unwrap();
+            let encrypted =
+                alice.encrypt_room_event_raw(room_id, "m.room.message", &content).await.unwrap();
+            let event = Raw::new(&json!({
+                "type": "m.room.encrypted",
+                "content": encrypted,
+                "event_id": format!("$encrypted{i}"),
+                "sender": "@alice:u.to",
+                "origin_server_ts": 1000,
+            }))
+            .unwrap()
+            .cast();
+            room.latest_encrypted_events.write().unwrap().push(event);
+        }
+
+        // The latest events can't be decrypted yet
+        assert!(client.refresh_all_latest_events().await.unwrap().is_empty());
+
+        // When we receive the room keys outside of a sync
+        let keys = alice.store().export_room_keys(|_| true).await.unwrap();
+        client
+            .olm_machine()
+            .await
+            .as_ref()
+            .unwrap()
+            .store()
+            .import_exported_room_keys(keys, |_, _| {})
+            .await
+            .unwrap();
+
+        // Then the latest events of both rooms are updated
+        let mut updated = client.refresh_all_latest_events().await.unwrap();
+        updated.sort();
+        assert_eq!(updated, room_ids.map(ToOwned::to_owned));
+
+        for room_id in room_ids {
+            let room = client.get_room(room_id).unwrap();
+            assert!(room.latest_encrypted_events().is_empty());
+            assert!(room.latest_event().is_some());
+        }
+    }
+
     // TODO: I wanted to write more tests here for decrypt_latest_events but I got
     // lost trying to set up my OlmMachine to be able to encrypt and decrypt
     // events. In the meantime, there are tests for the most difficult logic
@@ -1714,7 +3439,7 @@ mod tests {
     ) -> crate::Room {
         let mut sync_builder = SyncResponseBuilder::new();
         let response = sync_builder
-            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_timeline_event(
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
                 sync_timeline_event!({
                     "content": {
                         "displayname": "Alice",
@@ -1812,9 +3537,8 @@ mod tests {
 
         // Preamble: let the SDK know about the room.
         let mut sync_builder = SyncResponseBuilder::new();
-        let response = sync_builder
-            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id))
-            .build_sync_response();
+        let response =
+            sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id)).build_sync_response();
         client.receive_sync_response(response).await.unwrap();
 
         // When I process the result of a /members request that only contains an invited
@@ -1873,7 +3597,7 @@ mod tests {
         // Preamble: let the SDK know about the room, and that the invited user left it.
         let mut sync_builder = SyncResponseBuilder::new();
         let response = sync_builder
-            .add_joined_room(matrix_sdk_test::JoinedRoomBuilder::new(room_id).add_state_event(
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(
                 StateTestEvent::Custom(json!({
                     "content": {
                         "avatar_url": null,
@@ -1926,4 +3650,2052 @@ mod tests {
         assert_eq!(member.display_name().unwrap(), "Invited Alice");
         assert_eq!(member.avatar_url().unwrap().to_string(), "mxc://localhost/fewjilfewjil42");
     }
+
+    #[async_test]
+    async fn test_room_id_for_alias() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Alias),
+        )
+        .await;
+
+        // The canonical alias of the room is found locally.
+        assert_eq!(
+            client.room_id_for_alias(room_alias_id!("#tutorial:localhost")).as_deref(),
+            Some(room_id)
+        );
+
+        // An alias that no known room advertises must be resolved by the server.
+        assert!(client.room_id_for_alias(room_alias_id!("#unknown:localhost")).is_none());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_decryption_of_timeline_event_is_instrumented() {
+        use std::sync::{Arc, Mutex};
+
+        use tracing::{
+            span::{Attributes, Id, Record},
+            Event, Metadata, Subscriber,
+        };
+
+        /// A subscriber collecting the names of the spans that are created.
+        #[derive(Clone, Default)]
+        struct SpanCollector(Arc<Mutex<Vec<&'static str>>>);
+
+        impl Subscriber for SpanCollector {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.0.lock().unwrap();
+                spans.push(span.metadata().name());
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, _: &Event<'_>) {}
+
+            fn enter(&self, _: &Id) {}
+
+            fn exit(&self, _: &Id) {}
+        }
+
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let collector = SpanCollector::default();
+        let _guard = tracing::subscriber::set_default(collector.clone());
+
+        // When a sync contains an encrypted event we don't have the keys for,
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_timeline_event(sync_timeline_event!({
+                "content": {
+                    "algorithm": "m.megolm.v1.aes-sha2",
+                    "ciphertext": "AwgAEnACgAkLmt6qF84IK++J7UDH2Za1YVchHyprqTqsg",
+                    "device_id": "KCSDJNMSXW",
+                    "sender_key": "LvryVyoCjdONdBCi2vvoSbI34yTOx7YrCFACUEKoXnc",
+                    "session_id": "64H7XKokIx0ASkYDHZKlT5zd/Zccz/cQspPNdvnNULA"
+                },
+                "event_id": "$encrypted:example.org",
+                "origin_server_ts": 1432135524678u64,
+                "sender": "@bob:example.org",
+                "type": "m.room.encrypted",
+            })),
+        )
+        .await;
+
+        // Then the decryption attempt has been instrumented.
+        assert!(collector.0.lock().unwrap().contains(&"decrypt_sync_room_event"));
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_room_key_recipient_strategy() {
+        use matrix_sdk_crypto::CollectStrategy;
+
+        // By default, room keys are shared with all the devices.
+        let client = BaseClient::new();
+        assert_eq!(client.room_key_recipient_strategy, CollectStrategy::new_device_based(false));
+
+        // A custom strategy can be set when building the client,
+        let strategy = CollectStrategy::new_device_based(true);
+        let client = BaseClient::new().with_room_key_recipient_strategy(strategy.clone());
+        assert_eq!(client.room_key_recipient_strategy, strategy);
+
+        // And it is kept when cloning the client with a new state store.
+        let client = client.clone_with_in_memory_state_store();
+        assert_eq!(client.room_key_recipient_strategy, strategy);
+    }
+
+    #[async_test]
+    async fn test_replay_sync_responses() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let first = sync_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Alias))
+            .build_sync_response();
+        let second = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomTopic),
+            )
+            .build_sync_response();
+
+        // When replaying both responses, with a duplicate,
+        let response =
+            client.replay_sync_responses(vec![first.clone(), second.clone(), first]).await.unwrap();
+
+        // Then the room state reflects both responses,
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.canonical_alias().as_deref(), Some(room_alias_id!("#tutorial:localhost")));
+        assert!(room.topic().is_some());
+
+        // And the merged response contains the updates of both.
+        assert_eq!(response.rooms.join[room_id].state.len(), 2);
+        assert_eq!(client.sync_token().await, Some(second.next_batch));
+    }
+
+    #[async_test]
+    async fn test_push_rules_changed() {
+        let user_id = user_id!("@alice:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        // When a sync delivers push rules for the first time, they changed.
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_global_account_data_event(GlobalAccountDataTestEvent::PushRules)
+            .build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+        assert!(sync_response.push_rules_changed);
+
+        // When a sync delivers the same push rules again, they didn't change.
+        let response = sync_builder
+            .add_global_account_data_event(GlobalAccountDataTestEvent::PushRules)
+            .build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+        assert!(!sync_response.push_rules_changed);
+
+        // When a sync doesn't deliver push rules, they didn't change.
+        let response = sync_builder.build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+        assert!(!sync_response.push_rules_changed);
+    }
+
+    #[async_test]
+    async fn test_reset_sync_token() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Alias))
+            .build_sync_response();
+        let next_batch = response.next_batch.clone();
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(client.sync_token().await, Some(next_batch));
+
+        // When the sync token is reset,
+        client.reset_sync_token().await.unwrap();
+
+        // Then it's forgotten, in memory and in the store,
+        assert_eq!(client.sync_token().await, None);
+        assert!(client.store().get_kv_data(StateStoreDataKey::SyncToken).await.unwrap().is_none());
+
+        // But the room state is kept.
+        assert!(client.get_room(room_id).unwrap().canonical_alias().is_some());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_room_key_recipient_strategy_for_room() {
+        use matrix_sdk_crypto::CollectStrategy;
+
+        let room_id = room_id!("!sensitive:example.org");
+        let other_room_id = room_id!("!other:example.org");
+        let client = BaseClient::new();
+
+        // When a room overrides the strategy,
+        let strict = CollectStrategy::new_device_based(true);
+        client.set_room_key_recipient_strategy_for_room(room_id, Some(strict.clone()));
+
+        // Then it's used for that room only,
+        assert_eq!(client.room_key_recipient_strategy_for_room(room_id), strict);
+        assert_eq!(
+            client.room_key_recipient_strategy_for_room(other_room_id),
+            CollectStrategy::default()
+        );
+
+        // And the default strategy is used again once the override is removed.
+        client.set_room_key_recipient_strategy_for_room(room_id, None);
+        assert_eq!(
+            client.room_key_recipient_strategy_for_room(room_id),
+            CollectStrategy::default()
+        );
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_share_room_key_with_room_key_recipient_strategy_for_room() {
+        use matrix_sdk_crypto::CollectStrategy;
+
+        let room_id = room_id!("!sensitive:example.org");
+        let other_room_id = room_id!("!other:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Encryption),
+            )
+            .add_joined_room(
+                JoinedRoomBuilder::new(other_room_id).add_state_event(StateTestEvent::Encryption),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        // When a room overrides the strategy,
+        let strict = CollectStrategy::new_device_based(true);
+        client.set_room_key_recipient_strategy_for_room(room_id, Some(strict.clone()));
+
+        // And room keys are shared in both rooms,
+        client.share_room_key(room_id).await.unwrap();
+        client.share_room_key(other_room_id).await.unwrap();
+
+        // Then the outbound group sessions were created with the strategy of each
+        // room.
+        let olm_machine = client.olm_machine().await;
+        let store = olm_machine.as_ref().unwrap().store();
+
+        let session = store.get_outbound_group_session(room_id).await.unwrap().unwrap();
+        assert_eq!(session.settings().sharing_strategy, strict);
+
+        let session = store.get_outbound_group_session(other_room_id).await.unwrap().unwrap();
+        assert_eq!(session.settings().sharing_strategy, CollectStrategy::default());
+    }
+
+    #[async_test]
+    async fn test_state_changes_subscriber() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+        let mut state_changes = client.add_state_changes_subscriber();
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Alias),
+        )
+        .await;
+
+        let summary = state_changes.recv().await.unwrap();
+        assert!(summary.room_ids.contains(room_id));
+        assert_eq!(summary.event_type_counts.get("m.room.canonical_alias"), Some(&1));
+    }
+
+    #[async_test]
+    async fn test_state_snapshot_round_trip() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Alias),
+        )
+        .await;
+
+        // When a snapshot goes through its JSON representation,
+        let snapshot = client.export_state_snapshot().await;
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot = serde_json::from_str(&json).unwrap();
+
+        // And is imported in a fresh client,
+        let other_client = logged_in_base_client(Some(user_id)).await;
+        other_client.import_state_snapshot(snapshot).await.unwrap();
+
+        // Then both clients have the same state.
+        assert_eq!(other_client.sync_token().await, client.sync_token().await);
+        assert_eq!(other_client.rooms().len(), 1);
+
+        let room = other_client.get_room(room_id).unwrap();
+        assert_eq!(room.state(), RoomState::Joined);
+        assert_eq!(room.canonical_alias(), client.get_room(room_id).unwrap().canonical_alias());
+    }
+
+    #[async_test]
+    async fn test_import_state_snapshot_without_sync_token() {
+        let client = logged_in_base_client(None).await;
+
+        let response = SyncResponseBuilder::new().build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert!(client.sync_token().await.is_some());
+
+        // When a snapshot without a sync token is imported,
+        let snapshot = StateSnapshot { sync_token: None, room_infos: Vec::new() };
+        client.import_state_snapshot(snapshot).await.unwrap();
+
+        // Then the sync token is removed, in memory and in the store.
+        assert_eq!(client.sync_token().await, None);
+        assert!(client.store().get_kv_data(StateStoreDataKey::SyncToken).await.unwrap().is_none());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_olm_machine_created_on_demand() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = BaseClient::new();
+
+        // Set the session meta without creating the `OlmMachine`.
+        client
+            .store
+            .set_session_meta(
+                SessionMeta {
+                    user_id: user_id!("@alice:example.org").to_owned(),
+                    device_id: "FOOBAR".into(),
+                },
+                &client.roominfo_update_sender,
+            )
+            .await
+            .unwrap();
+        assert!(client.olm_machine.read().await.is_none());
+
+        // Encryption methods create the `OlmMachine` when they need it. The room
+        // is unknown, so sharing a room key fails after the machine was created.
+        assert_matches::assert_matches!(
+            client.share_room_key(room_id).await,
+            Err(crate::Error::EncryptionNotEnabled)
+        );
+        assert!(client.olm_machine().await.is_some());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_olm_machine_created_on_demand_concurrently() {
+        let client = BaseClient::new();
+
+        // Set the session meta without creating the `OlmMachine`.
+        client
+            .store
+            .set_session_meta(
+                SessionMeta {
+                    user_id: user_id!("@alice:example.org").to_owned(),
+                    device_id: "FOOBAR".into(),
+                },
+                &client.roominfo_update_sender,
+            )
+            .await
+            .unwrap();
+
+        // All the concurrent callers wait for the `OlmMachine` to be created.
+        let (first, second) = futures_util::join!(client.olm_machine(), client.olm_machine());
+        assert_eq!(first.as_ref().unwrap().device_id().as_str(), "FOOBAR");
+        assert_eq!(second.as_ref().unwrap().device_id().as_str(), "FOOBAR");
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_encryption_disabled_on_crypto_store_error() {
+        let user_id = user_id!("@alice:example.org");
+        let crypto_store = Arc::new(matrix_sdk_crypto::store::MemoryStore::new());
+
+        let client =
+            BaseClient::with_store_config(StoreConfig::new().crypto_store(crypto_store.clone()));
+        client
+            .set_session_meta(
+                SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                None,
+            )
+            .await
+            .unwrap();
+
+        // The crypto store now contains an account for another device, so the
+        // `OlmMachine` can't be created from it.
+        let other_session =
+            SessionMeta { user_id: user_id.to_owned(), device_id: "OTHERDEVICE".into() };
+
+        let client =
+            BaseClient::with_store_config(StoreConfig::new().crypto_store(crypto_store.clone()));
+        client.set_session_meta(other_session.clone(), None).await.unwrap_err();
+
+        let client = BaseClient::with_store_config(StoreConfig::new().crypto_store(crypto_store))
+            .with_encryption_disabled_on_crypto_store_error(true);
+        client.set_session_meta(other_session, None).await.unwrap();
+
+        assert!(client.logged_in());
+        assert!(client.is_encryption_disabled());
+
+        // The disabled encryption is respected, the `OlmMachine` isn't created on
+        // demand.
+        assert!(client.olm_machine().await.is_none());
+        assert!(client.is_encryption_disabled());
+    }
+
+    #[async_test]
+    async fn test_had_prior_sync() {
+        async fn activate(state_store: Arc<MemoryStore>) -> BaseClient {
+            logged_in_base_client_with_store_config(
+                None,
+                StoreConfig::new().state_store(state_store),
+            )
+            .await
+        }
+
+        let state_store = Arc::new(MemoryStore::new());
+
+        // The store doesn't have a sync token yet.
+        let client = BaseClient::with_store_config(StoreConfig::new());
+        assert!(!client.had_prior_sync());
+
+        let client = activate(state_store.clone()).await;
+        assert!(!client.had_prior_sync());
+
+        // Syncing doesn't change the value for the current client.
+        let response = SyncResponseBuilder::default().build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+        assert!(!client.had_prior_sync());
+
+        // The sync token is restored when the client is activated again.
+        let client = activate(state_store).await;
+        assert!(client.had_prior_sync());
+    }
+
+    #[async_test]
+    async fn test_media_url_rewriter() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = BaseClient::new().with_media_url_rewriter(|url| {
+            format!("https://proxy.example.org/{}", url.as_str().trim_start_matches("mxc://"))
+        });
+        client
+            .set_session_meta(
+                SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomAvatar),
+        )
+        .await;
+
+        // The URL is stored as-is.
+        let room = client.get_room(room_id).unwrap();
+        let avatar_url = room.avatar_url().unwrap();
+        assert_eq!(avatar_url.as_str(), "mxc://domain.com/JWEIFJgwEIhweiWJE");
+
+        // It is rewritten when it's read.
+        assert_eq!(
+            client.rewrite_media_url(&avatar_url).as_deref(),
+            Some("https://proxy.example.org/domain.com/JWEIFJgwEIhweiWJE")
+        );
+        assert!(BaseClient::new().rewrite_media_url(&avatar_url).is_none());
+    }
+
+    #[async_test]
+    async fn test_events_from_denied_servers_dropped() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = BaseClient::new().with_events_from_denied_servers_dropped(true);
+        client
+            .set_session_meta(
+                SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_state_event(StateTestEvent::Custom(json!({
+                        "content": { "allow": ["*"], "deny": ["evil.example.org"] },
+                        "event_id": "$acl:example.org",
+                        "origin_server_ts": 151957870,
+                        "sender": user_id,
+                        "state_key": "",
+                        "type": "m.room.server_acl",
+                    })))
+                    .add_timeline_event(sync_timeline_event!({
+                        "content": { "body": "Hello!", "msgtype": "m.text" },
+                        "event_id": "$allowed:example.org",
+                        "origin_server_ts": 151957878,
+                        "sender": "@bob:example.org",
+                        "type": "m.room.message",
+                    }))
+                    .add_timeline_event(sync_timeline_event!({
+                        "content": { "body": "Spam!", "msgtype": "m.text" },
+                        "event_id": "$denied:evil.example.org",
+                        "origin_server_ts": 151957879,
+                        "sender": "@mallory:evil.example.org",
+                        "type": "m.room.message",
+                    })),
+            )
+            .build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+
+        let timeline = &sync_response.rooms.join[room_id].timeline;
+        assert_eq!(timeline.events.len(), 1);
+        assert_eq!(
+            timeline.events[0].event_id().as_deref(),
+            Some(event_id!("$allowed:example.org"))
+        );
+    }
+
+    #[async_test]
+    async fn test_initial_sync_notifications_suppressed() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = BaseClient::new().with_initial_sync_notifications_suppressed(true);
+        client
+            .set_session_meta(
+                SessionMeta { user_id: user_id.to_owned(), device_id: "FOOBAR".into() },
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let message = sync_timeline_event!({
+            "content": {
+                "body": "Hello alice!",
+                "msgtype": "m.text",
+                "m.mentions": { "user_ids": [user_id] },
+            },
+            "event_id": "$message:example.org",
+            "origin_server_ts": 151957878,
+            "sender": "@bob:example.org",
+            "type": "m.room.message",
+        });
+
+        // When the initial sync contains an event that should notify,
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_state_event(StateTestEvent::Custom(json!({
+                        "content": { "membership": "join" },
+                        "event_id": "$alice_join:example.org",
+                        "origin_server_ts": 151957870,
+                        "sender": user_id,
+                        "state_key": user_id,
+                        "type": "m.room.member",
+                    })))
+                    .add_timeline_event(message.clone()),
+            )
+            .build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+
+        // Then there is no notification, but the push actions are computed,
+        assert!(sync_response.notifications.is_empty());
+        let timeline = &sync_response.rooms.join[room_id].timeline;
+        assert!(timeline.events[0].push_actions.iter().any(|action| action.should_notify()));
+
+        // But there are notifications in the next syncs.
+        let response = sync_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(message))
+            .build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+        assert_eq!(sync_response.notifications[room_id].len(), 1);
+    }
+
+    #[async_test]
+    async fn test_stored_profile() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "displayname": "Bob",
+                    "membership": "join",
+                },
+                "event_id": "$bob_join:example.org",
+                "origin_server_ts": 151957878,
+                "sender": "@bob:example.org",
+                "state_key": "@bob:example.org",
+                "type": "m.room.member",
+            }))),
+        )
+        .await;
+
+        let profile =
+            client.stored_profile(room_id, user_id!("@bob:example.org")).await.unwrap().unwrap();
+        assert_eq!(profile.as_original().unwrap().content.displayname.as_deref(), Some("Bob"));
+
+        assert!(client
+            .stored_profile(room_id, user_id!("@carol:example.org"))
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[async_test]
+    async fn test_custom_data() {
+        let client = logged_in_base_client(None).await;
+
+        assert!(client.get_custom_data("last_room").await.unwrap().is_none());
+
+        client.set_custom_data("last_room", b"!test:example.org".to_vec()).await.unwrap();
+        assert_eq!(
+            client.get_custom_data("last_room").await.unwrap().as_deref(),
+            Some(&b"!test:example.org"[..])
+        );
+
+        // The data is namespaced.
+        assert!(client.store().get_custom_value(b"last_room").await.unwrap().is_none());
+
+        client.remove_custom_data("last_room").await.unwrap();
+        assert!(client.get_custom_data("last_room").await.unwrap().is_none());
+    }
+
+    #[async_test]
+    async fn test_state_event_in_state_and_timeline_is_applied_once() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let store = Arc::new(TestStateStore::default());
+        let client = logged_in_base_client_with_store_config(
+            Some(user_id),
+            StoreConfig::new().state_store(store.clone()),
+        )
+        .await;
+
+        // Applying an invite removes the stored profile of the invited user, so
+        // every time it's applied the user is added to the profiles to delete.
+        let member_event = json!({
+            "content": {
+                "displayname": "Bob",
+                "membership": "invite",
+            },
+            "event_id": "$bob_invite:example.org",
+            "origin_server_ts": 151957878,
+            "sender": user_id,
+            "state_key": "@bob:example.org",
+            "type": "m.room.member",
+        });
+
+        // When the same member event is in the state and the timeline,
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_state_event(StateTestEvent::Custom(member_event.clone()))
+                    .add_timeline_event(sync_timeline_event!(member_event)),
+            )
+            .build_sync_response();
+        let sync_response = client.receive_sync_response(response).await.unwrap();
+
+        // Then it's still part of the timeline,
+        let joined_room = &sync_response.rooms.join[room_id];
+        assert_eq!(joined_room.timeline.events.len(), 1);
+
+        // But it's only applied once.
+        let changes = store.last_saved_changes.lock().unwrap().take().unwrap();
+        assert_eq!(changes.profiles_to_delete[room_id], [user_id!("@bob:example.org").to_owned()]);
+    }
+
+    #[async_test]
+    async fn test_is_display_name_ambiguous() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let member_event = |user_id: &str, display_name: &str| {
+            StateTestEvent::Custom(json!({
+                "content": {
+                    "displayname": display_name,
+                    "membership": "join",
+                },
+                "event_id": format!("${display_name}_{user_id}"),
+                "origin_server_ts": 151957878,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }))
+        };
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id)
+                .add_state_event(member_event("@bob:example.org", "Bob"))
+                .add_state_event(member_event("@other_bob:example.org", "Bob"))
+                .add_state_event(member_event("@carol:example.org", "Carol")),
+        )
+        .await;
+
+        // Members sharing a display name are ambiguous,
+        assert!(client
+            .is_display_name_ambiguous(room_id, user_id!("@bob:example.org"))
+            .await
+            .unwrap());
+        assert!(client
+            .is_display_name_ambiguous(room_id, user_id!("@other_bob:example.org"))
+            .await
+            .unwrap());
+
+        // But not the others.
+        assert!(!client
+            .is_display_name_ambiguous(room_id, user_id!("@carol:example.org"))
+            .await
+            .unwrap());
+        assert!(!client
+            .is_display_name_ambiguous(room_id, user_id!("@dave:example.org"))
+            .await
+            .unwrap());
+    }
+
+    #[async_test]
+    async fn test_validate_store() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let orphan_room_id = room_id!("!orphan:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": { "membership": "join" },
+                "event_id": "$alice_join:example.org",
+                "origin_server_ts": 151957878,
+                "sender": user_id,
+                "state_key": user_id,
+                "type": "m.room.member",
+            }))),
+        )
+        .await;
+
+        // A consistent store is valid.
+        assert!(client.validate_store().await.unwrap().is_valid());
+
+        // When a room without our own member event is in the store,
+        client.get_or_create_room(orphan_room_id, RoomState::Joined);
+
+        // Then it's reported.
+        let report = client.validate_store().await.unwrap();
+        assert_eq!(
+            report.issues,
+            vec![StoreValidationIssue::MissingOwnMember { room_id: orphan_room_id.to_owned() }]
+        );
+    }
+
+    #[async_test]
+    async fn test_user_id_and_device_id() {
+        let client = BaseClient::new();
+        assert!(client.user_id().is_none());
+        assert!(client.device_id().is_none());
+
+        let user_id = user_id!("@alice:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+        assert_eq!(client.user_id(), Some(user_id));
+        assert_eq!(client.device_id().map(|device_id| device_id.as_str()), Some("FOOBAR"));
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_typing() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+        let mut typing_receiver = client.subscribe_to_typing();
+
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id).add_ephemeral_event(EphemeralTestEvent::Typing),
+        )
+        .await;
+
+        let (typing_room_id, user_ids) = typing_receiver.try_recv().unwrap();
+        assert_eq!(typing_room_id, room_id);
+        assert_eq!(
+            user_ids,
+            vec![user_id!("@alice:matrix.org").to_owned(), user_id!("@bob:example.com").to_owned()]
+        );
+        assert!(typing_receiver.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_receipts() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+        let mut receipts_receiver = client.subscribe_to_receipts();
+
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id).add_ephemeral_event(EphemeralTestEvent::ReadReceipt),
+        )
+        .await;
+
+        assert_eq!(
+            receipts_receiver.try_recv().unwrap(),
+            ReceiptUpdate {
+                room_id: room_id.to_owned(),
+                user_id: user_id!("@example:localhost").to_owned(),
+                event_id: event_id!("$example").to_owned(),
+                receipt_type: ReceiptType::Read,
+                thread: ReceiptThread::Unthreaded,
+            }
+        );
+        assert!(receipts_receiver.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_room_encryption_enabled() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Member),
+        )
+        .await;
+        assert!(!client.get_room(room_id).unwrap().is_encrypted());
+
+        let mut encryption_enabled_receiver = client.subscribe_to_room_encryption_enabled();
+
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Encryption),
+        )
+        .await;
+
+        assert!(client.get_room(room_id).unwrap().is_encrypted());
+        assert_eq!(encryption_enabled_receiver.try_recv().unwrap(), room_id);
+        assert!(encryption_enabled_receiver.try_recv().is_err());
+
+        // Encryption being already enabled doesn't trigger a new update.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Encryption),
+        )
+        .await;
+        assert!(encryption_enabled_receiver.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_receive_members_at() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id),
+        )
+        .await;
+
+        let raw_member_event = json!({
+            "content": {
+                "displayname": "Old Alice",
+                "membership": "join"
+            },
+            "event_id": "$151800140517rfvjc:localhost",
+            "origin_server_ts": 151800140,
+            "room_id": room_id,
+            "sender": user_id,
+            "state_key": user_id,
+            "type": "m.room.member",
+        });
+        let response = api::membership::get_member_events::v3::Response::new(vec![Raw::from_json(
+            to_raw_value(&raw_member_event).unwrap(),
+        )]);
+
+        // The `at` parameter is required.
+        let mut request = api::membership::get_member_events::v3::Request::new(room_id.to_owned());
+        client.receive_members_at(room_id, &request, &response).await.unwrap_err();
+
+        request.at = Some("t392-516_47314_0_7_1_1_1_11444_1".to_owned());
+        client.receive_members_at(room_id, &request, &response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        let members = room.members_at("t392-516_47314_0_7_1_1_1_11444_1").unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].state_key(), user_id);
+        assert_eq!(
+            members[0].as_original().unwrap().content.displayname.as_deref(),
+            Some("Old Alice")
+        );
+        assert!(room.members_at("t1-other").is_none());
+
+        // The live member list is untouched.
+        assert!(room.get_member(user_id).await.unwrap().is_none());
+    }
+
+    #[async_test]
+    async fn test_timeline_event_handler() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        client.register_timeline_event_handler("org.example.custom", {
+            let received = received.clone();
+            move |room_id, event| {
+                let event_id = event.get_field::<OwnedEventId>("event_id").unwrap().unwrap();
+                received.lock().unwrap().push((room_id.to_owned(), event_id));
+            }
+        });
+
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id)
+                .add_timeline_event(sync_timeline_event!({
+                    "content": { "body": "hello" },
+                    "event_id": "$custom",
+                    "origin_server_ts": 151800140,
+                    "sender": "@bob:example.org",
+                    "type": "org.example.custom",
+                }))
+                .add_timeline_event(sync_timeline_event!({
+                    "content": { "body": "hello", "msgtype": "m.text" },
+                    "event_id": "$message",
+                    "origin_server_ts": 151800141,
+                    "sender": "@bob:example.org",
+                    "type": "m.room.message",
+                })),
+        )
+        .await;
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![(room_id.to_owned(), event_id!("$custom").to_owned())]
+        );
+    }
+
+    #[async_test]
+    async fn test_timeline_event_handler_not_called_when_saving_fails() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let store = Arc::new(TestStateStore::default());
+        let client = logged_in_base_client_with_store_config(
+            None,
+            StoreConfig::new().state_store(store.clone()),
+        )
+        .await;
+
+        let calls = Arc::new(Mutex::new(0));
+        client.register_timeline_event_handler("org.example.custom", {
+            let calls = calls.clone();
+            move |_, _| *calls.lock().unwrap() += 1
+        });
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        sync_builder.add_joined_room(JoinedRoomBuilder::new(room_id).add_timeline_event(
+            sync_timeline_event!({
+                "content": { "body": "hello" },
+                "event_id": "$custom",
+                "origin_server_ts": 151800140,
+                "sender": "@bob:example.org",
+                "type": "org.example.custom",
+            }),
+        ));
+        let response = sync_builder.build_sync_response();
+
+        // The handler isn't called for an event whose changes weren't saved.
+        store.fail_save_changes.store(true, Ordering::SeqCst);
+        client.receive_sync_response(response.clone()).await.unwrap_err();
+        assert_eq!(*calls.lock().unwrap(), 0);
+
+        // It's called once when the same response is received again successfully.
+        store.fail_save_changes.store(false, Ordering::SeqCst);
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(*calls.lock().unwrap(), 1);
+    }
+
+    #[async_test]
+    async fn test_is_user_ignored() {
+        let client = logged_in_base_client(None).await;
+        let bob = user_id!("@bob:example.org");
+        let carol = user_id!("@carol:example.org");
+
+        assert!(!client.is_user_ignored(bob));
+
+        let response = SyncResponseBuilder::default()
+            .add_global_account_data_event(GlobalAccountDataTestEvent::Custom(json!({
+                "content": { "ignored_users": { bob.as_str(): {} } },
+                "type": "m.ignored_user_list",
+            })))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert!(client.is_user_ignored(bob));
+        assert!(!client.is_user_ignored(carol));
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_store_errors() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let store = Arc::new(TestStateStore::default());
+        let client = logged_in_base_client_with_store_config(
+            None,
+            StoreConfig::new().state_store(store.clone()),
+        )
+        .await;
+        let mut store_errors = client.subscribe_to_store_errors();
+
+        // Saving the changes of a sync successfully doesn't emit anything.
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+        assert!(store_errors.try_recv().is_err());
+
+        // A failure of the store is emitted.
+        store.fail_save_changes.store(true, Ordering::SeqCst);
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+            )
+            .build_sync_response();
+        assert_matches::assert_matches!(
+            client.receive_sync_response(response).await,
+            Err(crate::Error::StateStore(_))
+        );
+
+        let event = store_errors.try_recv().unwrap();
+        assert_eq!(event.operation, "receive_sync_response");
+        assert!(event.message.contains("save_changes failed on purpose"));
+        assert!(store_errors.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_apply_changes_ignores_unknown_rooms() {
+        let room_id = room_id!("!unknown:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let mut changes = StateChanges::default();
+        changes.add_room(RoomInfo::new(room_id, RoomState::Joined));
+        client.apply_changes(&changes, false);
+
+        // Rooms are only created by the callers, not when applying their changes.
+        assert!(client.get_room(room_id).is_none());
+    }
+
+    #[async_test]
+    async fn test_receive_sync_response_store_failure_applies_nothing() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let new_room_id = room_id!("!new:example.org");
+        let store = Arc::new(TestStateStore::default());
+        let client = logged_in_base_client_with_store_config(
+            Some(user_id),
+            StoreConfig::new().state_store(store.clone()),
+        )
+        .await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+        let sync_token = client.sync_token().await;
+        assert!(sync_token.is_some());
+
+        let mut room_info_updates = client.roominfo_update_receiver();
+        let mut typing = client.subscribe_to_typing();
+        let mut receipts = client.subscribe_to_receipts();
+        let mut state_changes = client.add_state_changes_subscriber();
+        let room = client.get_room(room_id).unwrap();
+        let mut power_level_changes = pin!(room.subscribe_to_power_level_changes());
+
+        // Saving the changes of the next sync fails.
+        store.fail_save_changes.store(true, Ordering::SeqCst);
+        let response = sync_builder
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id)
+                    .add_state_event(StateTestEvent::RoomName)
+                    .add_state_event(StateTestEvent::PowerLevels)
+                    .add_ephemeral_event(EphemeralTestEvent::Typing)
+                    .add_ephemeral_event(EphemeralTestEvent::ReadReceipt),
+            )
+            .add_joined_room(JoinedRoomBuilder::new(new_room_id))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap_err();
+
+        // No observer was notified.
+        assert!(room_info_updates.try_recv().is_err());
+        assert!(typing.try_recv().is_err());
+        assert!(receipts.try_recv().is_err());
+        assert!(state_changes.try_recv().is_err());
+        assert!(power_level_changes.next().now_or_never().is_none());
+
+        // The in-memory state is left untouched.
+        assert!(room.name().is_none());
+        assert!(client.get_room(new_room_id).is_none());
+        assert_eq!(client.sync_token().await, sync_token);
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_ignored_user_effects() {
+        let client = logged_in_base_client(None).await;
+        let mut ignored_user_effects = client.subscribe_to_ignored_user_effects();
+
+        let ignored_user_list = |user_ids: serde_json::Value| {
+            GlobalAccountDataTestEvent::Custom(json!({
+                "content": { "ignored_users": user_ids },
+                "type": "m.ignored_user_list",
+            }))
+        };
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_global_account_data_event(ignored_user_list(json!({ "@bob:example.org": {} })))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(ignored_user_effects.try_recv().unwrap(), user_id!("@bob:example.org"));
+        assert!(ignored_user_effects.try_recv().is_err());
+
+        // Replacing a user in the list affects both users.
+        let response = sync_builder
+            .add_global_account_data_event(ignored_user_list(json!({ "@carol:example.org": {} })))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(ignored_user_effects.try_recv().unwrap(), user_id!("@carol:example.org"));
+        assert_eq!(ignored_user_effects.try_recv().unwrap(), user_id!("@bob:example.org"));
+        assert!(ignored_user_effects.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_room_upgrades() {
+        let room_id = room_id!("!old:example.org");
+        let client = logged_in_base_client(None).await;
+        let mut room_upgrades = client.subscribe_to_room_upgrades();
+
+        let tombstone = || {
+            StateTestEvent::Custom(json!({
+                "content": {
+                    "body": "This room has been replaced",
+                    "replacement_room": "!new:example.org",
+                },
+                "event_id": "$tombstone:example.org",
+                "origin_server_ts": 151800140,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.tombstone",
+            }))
+        };
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(tombstone()),
+        )
+        .await;
+
+        assert_eq!(
+            room_upgrades.try_recv().unwrap(),
+            (room_id.to_owned(), room_id!("!new:example.org").to_owned())
+        );
+        assert!(room_upgrades.try_recv().is_err());
+
+        // Receiving the same tombstone again in a later sync isn't a new room
+        // upgrade.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(tombstone()),
+        )
+        .await;
+        assert!(room_upgrades.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_room_summary() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        assert!(client.room_summary(room_id).is_none());
+
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id)
+                .add_state_event(StateTestEvent::RoomName)
+                .add_state_event(StateTestEvent::RoomAvatar)
+                .add_state_event(StateTestEvent::Encryption)
+                .set_unread_notifications_count(json!({
+                    "highlight_count": 1,
+                    "notification_count": 4,
+                })),
+        )
+        .await;
+
+        let summary = client.room_summary(room_id).unwrap();
+        assert_eq!(summary.room_id, room_id);
+        assert_eq!(summary.name.as_deref(), Some("room name"));
+        assert_eq!(
+            summary.avatar_url.as_deref().map(|url| url.as_str()),
+            Some("mxc://domain.com/JWEIFJgwEIhweiWJE")
+        );
+        assert_eq!(summary.unread_notification_counts.highlight_count, 1);
+        assert_eq!(summary.unread_notification_counts.notification_count, 4);
+        assert!(summary.is_encrypted);
+    }
+
+    #[async_test]
+    async fn test_retain_left_rooms_state() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+
+        for retain in [false, true] {
+            let client = logged_in_base_client_with_store_config(
+                Some(user_id),
+                StoreConfig::new().retain_left_rooms_state(retain),
+            )
+            .await;
+
+            receive_joined_room(
+                &client,
+                &mut SyncResponseBuilder::new(),
+                JoinedRoomBuilder::new(room_id),
+            )
+            .await;
+
+            let request = api::membership::get_member_events::v3::Request::new(room_id.to_owned());
+            let response = api::membership::get_member_events::v3::Response::new(Vec::new());
+            client.receive_all_members(room_id, &request, &response).await.unwrap();
+
+            let room = client.get_room(room_id).unwrap();
+            assert!(room.are_members_synced());
+
+            client.room_left(room_id).await.unwrap();
+
+            assert_eq!(room.state(), RoomState::Left);
+            assert_eq!(room.are_members_synced(), retain);
+            assert_eq!(room.is_state_fully_synced(), retain);
+        }
+    }
+
+    #[async_test]
+    async fn test_retain_left_rooms_state_on_own_leave_in_sync() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+
+        for retain in [false, true] {
+            let client = logged_in_base_client_with_store_config(
+                Some(user_id),
+                StoreConfig::new().retain_left_rooms_state(retain),
+            )
+            .await;
+
+            let mut sync_builder = SyncResponseBuilder::new();
+            let room = receive_joined_room(
+                &client,
+                &mut sync_builder,
+                JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+            )
+            .await;
+
+            let request = api::membership::get_member_events::v3::Request::new(room_id.to_owned());
+            let response = api::membership::get_member_events::v3::Response::new(Vec::new());
+            client.receive_all_members(room_id, &request, &response).await.unwrap();
+            assert!(room.are_members_synced());
+            assert!(room.is_state_fully_synced());
+
+            // We leave the room.
+            let response = sync_builder
+                .add_left_room(LeftRoomBuilder::new(room_id).add_timeline_event(
+                    sync_timeline_event!({
+                        "content": { "membership": "leave" },
+                        "event_id": "$alice_leave:example.org",
+                        "origin_server_ts": 151957878,
+                        "sender": user_id,
+                        "state_key": user_id,
+                        "type": "m.room.member",
+                    }),
+                ))
+                .build_sync_response();
+            client.receive_sync_response(response).await.unwrap();
+
+            assert_eq!(room.state(), RoomState::Left);
+            assert_eq!(room.are_members_synced(), retain);
+            assert_eq!(room.is_state_fully_synced(), retain);
+            // The state is kept in the store either way.
+            assert_eq!(room.name().as_deref(), Some("room name"));
+        }
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_decrypt_to_device_event_not_encrypted() {
+        let client = logged_in_base_client(None).await;
+
+        let event = Raw::from_json(
+            to_raw_value(&json!({
+                "content": { "body": "hello" },
+                "sender": "@bob:example.org",
+                "type": "org.example.custom",
+            }))
+            .unwrap(),
+        );
+
+        let decrypted = client.decrypt_to_device_event(event).await.unwrap();
+        assert_eq!(
+            decrypted.get_field::<String>("type").unwrap().as_deref(),
+            Some("org.example.custom")
+        );
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_decrypt_to_device_event() {
+        use std::collections::BTreeMap;
+
+        use matrix_sdk_crypto::{EncryptionSyncChanges, OlmMachine, OutgoingRequests};
+        use ruma::{
+            api::client::keys::{claim_keys, get_keys},
+            device_id,
+            encryption::DeviceKeyAlgorithm,
+            uint, TransactionId,
+        };
+
+        let user_id = user_id!("@bob:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+        let olm_machine = client.olm_machine().await;
+        let bob = olm_machine.as_ref().unwrap();
+
+        // Let Bob generate one-time keys, as if the server told it none were
+        // uploaded.
+        bob.receive_sync_changes(EncryptionSyncChanges {
+            to_device_events: Vec::new(),
+            changed_devices: &Default::default(),
+            one_time_keys_counts: &BTreeMap::from([(
+                DeviceKeyAlgorithm::SignedCurve25519,
+                uint!(0),
+            )]),
+            unused_fallback_keys: None,
+            next_batch_token: None,
+        })
+        .await
+        .unwrap();
+
+        let requests = bob.outgoing_requests().await.unwrap();
+        let upload = requests
+            .iter()
+            .find_map(|request| match request.request() {
+                OutgoingRequests::KeysUpload(upload) => Some(upload),
+                _ => None,
+            })
+            .unwrap();
+
+        // Alice learns about Bob's device and claims one of its one-time keys, to
+        // establish an Olm session with it.
+        let alice =
+            OlmMachine::new(user_id!("@alice:example.org"), device_id!("ALICEDEVICE")).await;
+        let mut keys_query = get_keys::v3::Response::new();
+        keys_query.device_keys = BTreeMap::from([(
+            bob.user_id().to_owned(),
+            BTreeMap::from([(bob.device_id().to_owned(), upload.device_keys.clone().unwrap())]),
+        )]);
+        alice.mark_request_as_sent(&TransactionId::new(), &keys_query).await.unwrap();
+
+        let (key_id, one_time_key) = upload.one_time_keys.first_key_value().unwrap();
+        let keys_claim = claim_keys::v3::Response::new(BTreeMap::from([(
+            bob.user_id().to_owned(),
+            BTreeMap::from([(
+                bob.device_id().to_owned(),
+                BTreeMap::from([(key_id.clone(), one_time_key.clone())]),
+            )]),
+        )]));
+        alice.mark_request_as_sent(&TransactionId::new(), &keys_claim).await.unwrap();
+
+        let content = alice
+            .get_device(bob.user_id(), bob.device_id(), None)
+            .await
+            .unwrap()
+            .unwrap()
+            .encrypt_event_raw("org.example.custom", &json!({ "body": "hello" }))
+            .await
+            .unwrap();
+        drop(olm_machine);
+
+        let event = Raw::from_json(
+            to_raw_value(&json!({
+                "content": content,
+                "sender": alice.user_id(),
+                "type": "m.room.encrypted",
+            }))
+            .unwrap(),
+        );
+
+        let decrypted = client.decrypt_to_device_event(event).await.unwrap();
+        assert_eq!(
+            decrypted.get_field::<String>("type").unwrap().as_deref(),
+            Some("org.example.custom")
+        );
+        assert_eq!(
+            decrypted.get_field::<serde_json::Value>("content").unwrap(),
+            Some(json!({ "body": "hello" }))
+        );
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_decrypt_to_device_event_unknown_session() {
+        let client = logged_in_base_client(None).await;
+
+        // An Olm message from a device we never established a session with
+        // can't be decrypted, the event is returned as-is.
+        let event = Raw::from_json(
+            to_raw_value(&json!({
+                "content": {
+                    "algorithm": "m.olm.v1.curve25519-aes-sha2",
+                    "ciphertext": {},
+                    "sender_key": "Nn0L2hkcCMFKqynTjyGsJbth7QrVmX3lbrksMkrGOAw",
+                },
+                "sender": "@bob:example.org",
+                "type": "m.room.encrypted",
+            }))
+            .unwrap(),
+        );
+
+        let decrypted = client.decrypt_to_device_event(event).await.unwrap();
+        assert_eq!(
+            decrypted.get_field::<String>("type").unwrap().as_deref(),
+            Some("m.room.encrypted")
+        );
+    }
+
+    #[async_test]
+    async fn test_total_unread_counts() {
+        let room_id_a = room_id!("!a:example.org");
+        let room_id_b = room_id!("!b:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_joined_room(JoinedRoomBuilder::new(room_id_a).set_unread_notifications_count(
+                json!({ "highlight_count": 1, "notification_count": 2 }),
+            ))
+            .add_joined_room(JoinedRoomBuilder::new(room_id_b).set_unread_notifications_count(
+                json!({ "highlight_count": 0, "notification_count": 3 }),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let total = client.total_unread_counts();
+        assert_eq!(total.highlight_count, 1);
+        assert_eq!(total.notification_count, 5);
+
+        let mut subscriber = client.subscribe_to_total_unread_counts();
+        assert_eq!(subscriber.get(), total);
+
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id_b).set_unread_notifications_count(
+                json!({ "highlight_count": 2, "notification_count": 4 }),
+            ),
+        )
+        .await;
+
+        let total = subscriber.next_now();
+        assert_eq!(total.highlight_count, 3);
+        assert_eq!(total.notification_count, 6);
+
+        // Updating a room without going through a sync keeps the total in sync
+        // with the counts of the rooms.
+        client.room_left(room_id_a).await.unwrap();
+        client.set_room_name_local(room_id_b, "New name".to_owned()).await.unwrap();
+        assert_eq!(subscriber.get(), client.total_unread_counts());
+
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id_b).set_unread_notifications_count(
+                json!({ "highlight_count": 0, "notification_count": 1 }),
+            ),
+        )
+        .await;
+
+        let total = subscriber.next_now();
+        assert_eq!(total, client.total_unread_counts());
+        assert_eq!(total.highlight_count, 1);
+        assert_eq!(total.notification_count, 3);
+    }
+
+    #[async_test]
+    async fn test_needs_member_resync() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+        assert!(!client.needs_member_resync(room_id));
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).set_timeline_limited(),
+        )
+        .await;
+        assert!(client.needs_member_resync(room_id));
+
+        let request = api::membership::get_member_events::v3::Request::new(room_id.to_owned());
+        let response = api::membership::get_member_events::v3::Response::new(Vec::new());
+        client.receive_all_members(room_id, &request, &response).await.unwrap();
+        assert!(!client.needs_member_resync(room_id));
+    }
+
+    #[async_test]
+    async fn test_receive_account_data() {
+        use ruma::push::RuleKind;
+
+        let client = logged_in_base_client(None).await;
+
+        // The master rule is disabled by default.
+        let push_rules = client.get_push_rules(&Default::default()).await.unwrap();
+        assert!(!push_rules.get(RuleKind::Override, ".m.rule.master").unwrap().enabled());
+
+        let push_rules_event = json!({
+            "content": {
+                "global": {
+                    "override": [{
+                        "actions": [],
+                        "conditions": [],
+                        "default": true,
+                        "enabled": true,
+                        "rule_id": ".m.rule.master",
+                    }],
+                },
+            },
+            "type": "m.push_rules",
+        });
+        client
+            .receive_account_data(&[Raw::from_json(to_raw_value(&push_rules_event).unwrap())])
+            .await
+            .unwrap();
+
+        let push_rules = client.get_push_rules(&Default::default()).await.unwrap();
+        assert!(push_rules.get(RuleKind::Override, ".m.rule.master").unwrap().enabled());
+        assert!(client.sync_token().await.is_none());
+    }
+
+    #[async_test]
+    async fn test_unchanged_room_info_is_not_notified() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+        let mut room_info_updates = client.roominfo_update_receiver();
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+        )
+        .await;
+
+        assert_eq!(room_info_updates.try_recv().unwrap().room_id, room_id);
+        assert!(room_info_updates.try_recv().is_err());
+
+        // Receiving the same state again doesn't change the room info.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+        )
+        .await;
+
+        assert!(room_info_updates.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_cleared_room_account_data() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room = receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id)
+                .add_account_data(RoomAccountDataTestEvent::Custom(json!({
+                    "content": { "tags": { "m.favourite": { "order": 0.5 } } },
+                    "type": "m.tag",
+                })))
+                .add_account_data(RoomAccountDataTestEvent::Custom(json!({
+                    "content": { "unread": true },
+                    "type": "com.famedly.marked_unread",
+                }))),
+        )
+        .await;
+        assert!(room.is_favourite());
+        assert_eq!(room.tag_order(&TagName::Favorite), Some(0.5));
+        assert!(room.is_marked_unread());
+
+        // Clearing the account data resets the state of the room.
+        let mut room_info_updates = client.roominfo_update_receiver();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id)
+                .add_account_data(RoomAccountDataTestEvent::Custom(json!({
+                    "content": {},
+                    "type": "m.tag",
+                })))
+                .add_account_data(RoomAccountDataTestEvent::Custom(json!({
+                    "content": {},
+                    "type": "com.famedly.marked_unread",
+                }))),
+        )
+        .await;
+
+        assert!(!room.is_favourite());
+        assert_eq!(room.tag_order(&TagName::Favorite), None);
+        assert!(!room.is_marked_unread());
+        assert_eq!(room_info_updates.try_recv().unwrap().room_id, room_id);
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_room_encryption_settings() {
+        use std::time::Duration;
+
+        use ruma::{events::room::history_visibility::HistoryVisibility, EventEncryptionAlgorithm};
+
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        // Unknown rooms don't have any settings.
+        assert!(client.room_encryption_settings(room_id).is_none());
+
+        // Rooms that aren't encrypted don't have any settings either.
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+        assert!(client.room_encryption_settings(room_id).is_none());
+
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id)
+                .add_state_event(StateTestEvent::Encryption)
+                .add_state_event(StateTestEvent::Custom(json!({
+                    "content": {
+                        "history_visibility": "shared"
+                    },
+                    "event_id": "$h1",
+                    "origin_server_ts": 151800140,
+                    "sender": user_id,
+                    "state_key": "",
+                    "type": "m.room.history_visibility",
+                }))),
+        )
+        .await;
+
+        let settings = client.room_encryption_settings(room_id).unwrap();
+        assert_eq!(settings.algorithm, EventEncryptionAlgorithm::MegolmV1AesSha2);
+        assert_eq!(settings.history_visibility, HistoryVisibility::Shared);
+        assert_eq!(settings.rotation_period, Duration::from_millis(604800000));
+        assert_eq!(settings.rotation_period_msgs, 100);
+    }
+
+    #[async_test]
+    async fn test_rooms_with_highlights() {
+        let room_id_a = room_id!("!a:example.org");
+        let room_id_b = room_id!("!b:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(JoinedRoomBuilder::new(room_id_a).set_unread_notifications_count(
+                json!({ "highlight_count": 2, "notification_count": 3 }),
+            ))
+            .add_joined_room(JoinedRoomBuilder::new(room_id_b).set_unread_notifications_count(
+                json!({ "highlight_count": 0, "notification_count": 4 }),
+            ))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(client.rooms_with_highlights(), [(room_id_a.to_owned(), 2)]);
+    }
+
+    #[async_test]
+    async fn test_clear_all_unread_markers() {
+        let room_id_a = room_id!("!a:example.org");
+        let room_id_b = room_id!("!b:example.org");
+        let room_id_c = room_id!("!c:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let marked_unread = || {
+            RoomAccountDataTestEvent::Custom(json!({
+                "content": { "unread": true },
+                "type": "com.famedly.marked_unread",
+            }))
+        };
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(JoinedRoomBuilder::new(room_id_a).add_account_data(marked_unread()))
+            .add_joined_room(JoinedRoomBuilder::new(room_id_b).add_account_data(marked_unread()))
+            .add_joined_room(JoinedRoomBuilder::new(room_id_c))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert!(client.get_room(room_id_a).unwrap().is_marked_unread());
+        assert!(client.get_room(room_id_b).unwrap().is_marked_unread());
+        assert!(!client.get_room(room_id_c).unwrap().is_marked_unread());
+
+        let mut room_info_updates = client.roominfo_update_receiver();
+
+        let mut contents = client.clear_all_unread_markers().await.unwrap();
+        contents.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        assert_eq!(contents.len(), 2);
+        assert_eq!(contents[0].0, room_id_a);
+        assert_eq!(contents[1].0, room_id_b);
+        assert!(contents.iter().all(|(_, content)| !content.unread));
+
+        assert!(!client.get_room(room_id_a).unwrap().is_marked_unread());
+        assert!(!client.get_room(room_id_b).unwrap().is_marked_unread());
+
+        let mut updated_room_ids = vec![
+            room_info_updates.try_recv().unwrap().room_id,
+            room_info_updates.try_recv().unwrap().room_id,
+        ];
+        updated_room_ids.sort();
+        assert_eq!(updated_room_ids, [room_id_a, room_id_b]);
+        assert!(room_info_updates.try_recv().is_err());
+
+        // Nothing is returned when no room is marked as unread.
+        assert!(client.clear_all_unread_markers().await.unwrap().is_empty());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_subscribe_to_device_list_changes() {
+        let client = logged_in_base_client(None).await;
+        let mut device_list_changes = client.subscribe_to_device_list_changes();
+
+        let response = SyncResponseBuilder::default()
+            .add_change_device(user_id!("@bob:example.org"))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(device_list_changes.try_recv().unwrap(), user_id!("@bob:example.org"));
+        assert!(device_list_changes.try_recv().is_err());
+    }
+
+    #[cfg(feature = "e2e-encryption")]
+    #[async_test]
+    async fn test_device_list_changes_not_sent_when_saving_fails() {
+        let store = Arc::new(TestStateStore::default());
+        let client = logged_in_base_client_with_store_config(
+            None,
+            StoreConfig::new().state_store(store.clone()),
+        )
+        .await;
+        let mut device_list_changes = client.subscribe_to_device_list_changes();
+
+        let response = SyncResponseBuilder::default()
+            .add_change_device(user_id!("@bob:example.org"))
+            .build_sync_response();
+
+        // The change isn't sent for a sync whose changes weren't saved.
+        store.fail_save_changes.store(true, Ordering::SeqCst);
+        client.receive_sync_response(response.clone()).await.unwrap_err();
+        assert!(device_list_changes.try_recv().is_err());
+
+        // It's sent once when the same response is received again successfully.
+        store.fail_save_changes.store(false, Ordering::SeqCst);
+        client.receive_sync_response(response).await.unwrap();
+        assert_eq!(device_list_changes.try_recv().unwrap(), user_id!("@bob:example.org"));
+        assert!(device_list_changes.try_recv().is_err());
+    }
+
+    #[async_test]
+    async fn test_set_room_name_local() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        assert!(client
+            .set_room_name_local(room_id, "New name".to_owned())
+            .await
+            .unwrap()
+            .is_none());
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room = receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+        )
+        .await;
+        assert_eq!(room.cached_display_name(), Some(DisplayName::Named("room name".to_owned())));
+
+        let mut room_info_updates = client.roominfo_update_receiver();
+        let display_name =
+            client.set_room_name_local(room_id, "New name".to_owned()).await.unwrap();
+
+        assert_eq!(display_name, Some(DisplayName::Named("New name".to_owned())));
+        assert_eq!(room.name().as_deref(), Some("room name"));
+        assert_eq!(room.cached_display_name(), Some(DisplayName::Named("New name".to_owned())));
+        assert_eq!(room_info_updates.try_recv().unwrap().room_id, room_id);
+
+        // The local name is kept when another state event is received, and it is not
+        // saved in the store.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomTopic),
+        )
+        .await;
+        assert_eq!(
+            room.compute_display_name().await.unwrap(),
+            DisplayName::Named("New name".to_owned())
+        );
+
+        let room_infos = client.store().get_room_infos().await.unwrap();
+        assert_eq!(room_infos.len(), 1);
+        assert_eq!(room_infos[0].name(), Some("room name"));
+
+        // The local name is replaced by the one of a new `m.room.name` event.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": { "name": "Server name" },
+                "event_id": "$new_room_name",
+                "origin_server_ts": 151957878,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.name",
+            }))),
+        )
+        .await;
+        assert_eq!(room.name().as_deref(), Some("Server name"));
+        assert_eq!(room.cached_display_name(), Some(DisplayName::Named("Server name".to_owned())));
+    }
+
+    #[cfg(feature = "experimental-sliding-sync")]
+    #[async_test]
+    async fn test_rooms_by_recent_activity() {
+        use crate::{latest_event::LatestEvent, Room};
+
+        let client = logged_in_base_client(None).await;
+
+        for (room_id, timestamp) in [
+            (room_id!("!old:example.org"), Some(1000)),
+            (room_id!("!recent:example.org"), Some(3000)),
+            (room_id!("!empty:example.org"), None),
+            (room_id!("!middle:example.org"), Some(2000)),
+        ] {
+            let room = client.get_or_create_room(room_id, RoomState::Joined);
+
+            if let Some(timestamp) = timestamp {
+                let event = sync_timeline_event!({
+                    "content": { "body": "hello", "msgtype": "m.text" },
+                    "event_id": format!("$event_{timestamp}"),
+                    "origin_server_ts": timestamp,
+                    "sender": "@example:localhost",
+                    "type": "m.room.message",
+                });
+                let mut room_info = room.clone_info();
+                room_info.latest_event =
+                    Some(Box::new(LatestEvent::new(SyncTimelineEvent::new(event))));
+                room.set_room_info(room_info, false);
+            }
+        }
+
+        let room_ids = |rooms: Vec<Room>| {
+            rooms.iter().map(|room| room.room_id().to_string()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            room_ids(client.rooms_by_recent_activity(10)),
+            [
+                "!recent:example.org",
+                "!middle:example.org",
+                "!old:example.org",
+                "!empty:example.org"
+            ]
+        );
+        assert_eq!(
+            room_ids(client.rooms_by_recent_activity(2)),
+            ["!recent:example.org", "!middle:example.org"]
+        );
+    }
+
+    #[async_test]
+    async fn test_receive_sync_response_cancelled_before_saving_changes() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let response = || {
+            SyncResponseBuilder::default()
+                .add_joined_room(
+                    JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+                )
+                .build_sync_response()
+        };
+
+        // Hold the sync lock so the changes can't be saved.
+        let sync_lock = client.sync_lock().lock().await;
+        let mut room_info_updates = client.roominfo_update_receiver();
+
+        {
+            let mut sync_future = pin!(client.receive_sync_response(response()));
+            assert!(sync_future.as_mut().now_or_never().is_none());
+        }
+
+        drop(sync_lock);
+
+        // Give a task that would save the changes a chance to run.
+        tokio::task::yield_now().await;
+
+        // The cancelled sync left no trace, in memory or in the store.
+        assert!(client.get_room(room_id).is_none());
+        assert!(client.sync_token().await.is_none());
+        assert!(client.store().get_room_infos().await.unwrap().is_empty());
+        assert!(room_info_updates.try_recv().is_err());
+
+        // The same response can be received again.
+        client.receive_sync_response(response()).await.unwrap();
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.name().as_deref(), Some("room name"));
+    }
+
+    #[async_test]
+    async fn test_receive_sync_response_cancelled_while_saving_changes() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let store = Arc::new(TestStateStore::default());
+        let client = logged_in_base_client_with_store_config(
+            None,
+            StoreConfig::new().state_store(store.clone()),
+        )
+        .await;
+        let mut room_info_updates = client.roominfo_update_receiver();
+
+        let response = SyncResponseBuilder::default()
+            .add_joined_room(
+                JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+            )
+            .build_sync_response();
+        let next_batch = response.next_batch.clone();
+
+        // Hold the gate so the changes are stuck in the middle of being saved.
+        let gate = store.save_changes_gate.lock().await;
+        let save_changes_calls = store.save_changes_calls.load(Ordering::SeqCst);
+
+        {
+            let mut sync_future = pin!(client.receive_sync_response(response));
+            assert!(sync_future.as_mut().now_or_never().is_none());
+
+            // Let the task saving the changes start writing them to the store.
+            tokio::task::yield_now().await;
+            assert_eq!(store.save_changes_calls.load(Ordering::SeqCst), save_changes_calls + 1);
+        }
+
+        // The future was dropped during the store write, but the task finishes
+        // saving the changes and applies them in memory.
+        drop(gate);
+        drop(client.sync_lock().lock().await);
+
+        assert_eq!(client.sync_token().await, Some(next_batch));
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.name().as_deref(), Some("room name"));
+        assert_eq!(client.store().get_room_infos().await.unwrap().len(), 1);
+        assert_eq!(room_info_updates.try_recv().unwrap().room_id, room_id);
+    }
+
+    #[async_test]
+    async fn test_can_redact_event() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let own_message = SyncTimelineEvent::new(sync_timeline_event!({
+            "content": { "body": "hello", "msgtype": "m.text" },
+            "event_id": "$own",
+            "origin_server_ts": 151800140,
+            "sender": user_id,
+            "type": "m.room.message",
+        }));
+        let other_message = SyncTimelineEvent::new(sync_timeline_event!({
+            "content": { "body": "hello", "msgtype": "m.text" },
+            "event_id": "$other",
+            "origin_server_ts": 151800141,
+            "sender": "@bob:example.org",
+            "type": "m.room.message",
+        }));
+
+        // Unknown room.
+        assert!(!client.can_redact_event(room_id, &own_message).await.unwrap());
+
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "redact": 50,
+                    "users": { "@bob:example.org": 100 },
+                    "users_default": 0,
+                },
+                "event_id": "$power_levels",
+                "origin_server_ts": 151800139,
+                "sender": "@bob:example.org",
+                "state_key": "",
+                "type": "m.room.power_levels",
+            }))),
+        )
+        .await;
+
+        assert!(client.can_redact_event(room_id, &own_message).await.unwrap());
+        assert!(!client.can_redact_event(room_id, &other_message).await.unwrap());
+    }
 }
@@ -61,4 +61,9 @@ pub enum Error {
     /// function with invalid parameters
     #[error("receive_all_members function was called with invalid parameters")]
     InvalidReceiveMembersParameters,
+
+    /// A task that had to run to completion was cancelled before it completed,
+    /// e.g. because the runtime is shutting down.
+    #[error("a task was cancelled before it completed")]
+    TaskCancelled,
 }
@@ -14,7 +14,7 @@ use ruma::{
         call::{invite::SyncCallInviteEvent, notify::SyncCallNotifyEvent},
         relation::RelationType,
     },
-    MxcUri, OwnedEventId,
+    MilliSecondsSinceUnixEpoch, MxcUri, OwnedEventId,
 };
 use serde::{Deserialize, Serialize};
 
@@ -226,6 +226,28 @@ impl LatestEvent {
         self.event.event_id()
     }
 
+    /// Get the timestamp of the event, as set by the homeserver that
+    /// originated it.
+    pub fn origin_server_ts(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.event.event.get_field("origin_server_ts").ok().flatten()
+    }
+
+    /// Whether this latest event is known to be identical to `other`.
+    ///
+    /// The events are compared by their JSON, and the sender profiles by their
+    /// event ID.
+    pub(crate) fn is_same_as(&self, other: &Self) -> bool {
+        self.event.event.json().get() == other.event.event.json().get()
+            && self.event.encryption_info.is_some() == other.event.encryption_info.is_some()
+            && self.event.push_actions.len() == other.event.push_actions.len()
+            && match (&self.sender_profile, &other.sender_profile) {
+                (None, None) => true,
+                (Some(a), Some(b)) => a.is_same_event(b),
+                _ => false,
+            }
+            && self.sender_name_is_ambiguous == other.sender_name_is_ambiguous
+    }
+
     /// Check whether [`Self`] has a sender profile.
     pub fn has_sender_profile(&self) -> bool {
         self.sender_profile.is_some()
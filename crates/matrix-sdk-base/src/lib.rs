@@ -45,19 +45,21 @@ mod utils;
 #[cfg(feature = "uniffi")]
 uniffi::setup_scaffolding!();
 
-pub use client::BaseClient;
+pub use client::{BaseClient, MediaUrlRewriter, TimelineEventHandler};
 #[cfg(any(test, feature = "testing"))]
 pub use http;
 #[cfg(feature = "e2e-encryption")]
 pub use matrix_sdk_crypto as crypto;
 pub use once_cell;
 pub use rooms::{
-    DisplayName, Room, RoomCreateWithCreatorEventContent, RoomHero, RoomInfo, RoomInfoUpdate,
-    RoomMember, RoomMemberships, RoomState, RoomStateFilter,
+    DisplayName, ReceiptUpdate, Room, RoomAvatarSource, RoomCreateWithCreatorEventContent,
+    RoomHero, RoomInfo, RoomInfoUpdate, RoomMember, RoomMemberships, RoomState, RoomStateCounts,
+    RoomStateFilter, RoomSummaryView, ThirdPartyInvite,
 };
 pub use store::{
-    ComposerDraft, ComposerDraftType, StateChanges, StateStore, StateStoreDataKey,
-    StateStoreDataValue, StoreError,
+    ComposerDraft, ComposerDraftType, StateChanges, StateChangesSummary, StateSnapshot, StateStore,
+    StateStoreDataKey, StateStoreDataValue, StoreError, StoreErrorEvent, StoreValidationIssue,
+    StoreValidationReport,
 };
 pub use utils::{
     MinimalRoomMemberEvent, MinimalStateEvent, OriginalMinimalStateEvent, RedactedMinimalStateEvent,
@@ -11,7 +11,10 @@ use std::{
 
 use bitflags::bitflags;
 pub use members::RoomMember;
-pub use normal::{Room, RoomHero, RoomInfo, RoomInfoUpdate, RoomState, RoomStateFilter};
+pub use normal::{
+    ReceiptUpdate, Room, RoomAvatarSource, RoomHero, RoomInfo, RoomInfoUpdate, RoomState,
+    RoomStateCounts, RoomStateFilter, RoomSummaryView, ThirdPartyInvite,
+};
 use ruma::{
     assign,
     events::{
@@ -27,6 +30,7 @@ use ruma::{
             join_rules::RoomJoinRulesEventContent,
             member::MembershipState,
             name::RoomNameEventContent,
+            third_party_invite::RoomThirdPartyInviteEventContent,
             tombstone::RoomTombstoneEventContent,
             topic::RoomTopicEventContent,
         },
@@ -105,6 +109,10 @@ pub struct BaseRoomInfo {
     /// memberships.
     #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
     pub(crate) rtc_member: BTreeMap<OwnedUserId, MinimalStateEvent<CallMemberEventContent>>,
+    /// The pending third-party invites of this room, by token.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub(crate) third_party_invites:
+        BTreeMap<String, MinimalStateEvent<RoomThirdPartyInviteEventContent>>,
     /// Whether this room has been manually marked as unread.
     #[serde(default)]
     pub(crate) is_marked_unread: bool,
@@ -114,6 +122,9 @@ pub struct BaseRoomInfo {
     /// others, and this field collects them.
     #[serde(skip_serializing_if = "RoomNotableTags::is_empty", default)]
     pub(crate) notable_tags: RoomNotableTags,
+    /// The order of the room in each of its tags, if any.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub(crate) tag_orders: BTreeMap<TagName, f64>,
 }
 
 impl BaseRoomInfo {
@@ -133,6 +144,58 @@ impl BaseRoomInfo {
         }
     }
 
+    /// Whether this base room info is known to be identical to `other`.
+    ///
+    /// State events are compared by their ID, so this might return `false`
+    /// for identical infos built from events without an ID.
+    pub(crate) fn is_same_as(&self, other: &Self) -> bool {
+        let Self {
+            avatar,
+            canonical_alias,
+            create,
+            dm_targets,
+            encryption,
+            guest_access,
+            history_visibility,
+            join_rules,
+            max_power_level,
+            name,
+            tombstone,
+            topic,
+            rtc_member,
+            third_party_invites,
+            is_marked_unread,
+            notable_tags,
+            tag_orders,
+        } = self;
+
+        avatar.has_same_event(&other.avatar)
+            && canonical_alias.has_same_event(&other.canonical_alias)
+            && create.has_same_event(&other.create)
+            && *dm_targets == other.dm_targets
+            && match (encryption, &other.encryption) {
+                (None, None) => true,
+                (Some(a), Some(b)) => {
+                    a.algorithm == b.algorithm
+                        && a.rotation_period_ms == b.rotation_period_ms
+                        && a.rotation_period_msgs == b.rotation_period_msgs
+                }
+                _ => false,
+            }
+            && guest_access.has_same_event(&other.guest_access)
+            && history_visibility.has_same_event(&other.history_visibility)
+            && join_rules.has_same_event(&other.join_rules)
+            && *max_power_level == other.max_power_level
+            && name.has_same_event(&other.name)
+            && tombstone.has_same_event(&other.tombstone)
+            && topic.has_same_event(&other.topic)
+            && are_same_events(rtc_member, &other.rtc_member)
+            && are_same_events(third_party_invites, &other.third_party_invites)
+            && *is_marked_unread == other.is_marked_unread
+            && notable_tags.bits() == other.notable_tags.bits()
+            && *tag_orders == other.tag_orders
+    }
+
     /// Handle a state event for this room and update our info accordingly.
     ///
     /// Returns true if the event modified the info, false otherwise.
@@ -191,6 +254,22 @@ impl BaseRoomInfo {
                     ev.as_original().is_some_and(|o| !o.content.active_memberships(None).is_empty())
                 });
             }
+            AnySyncStateEvent::RoomThirdPartyInvite(i) => {
+                self.third_party_invites.insert(i.state_key().clone(), i.into());
+            }
+            AnySyncStateEvent::RoomMember(m) => {
+                // A member event with a signed third-party invite resolves the
+                // pending invite with the same token.
+                let Some(token) = m
+                    .as_original()
+                    .and_then(|m| m.content.third_party_invite.as_ref())
+                    .map(|invite| &invite.signed.token)
+                else {
+                    return false;
+                };
+
+                return self.third_party_invites.remove(token).is_some();
+            }
             _ => return false,
         }
 
@@ -280,6 +359,7 @@ impl BaseRoomInfo {
             self.topic.as_mut().unwrap().redact(&room_version);
         } else {
             self.rtc_member.retain(|_, member_event| member_event.event_id() != Some(redacts));
+            self.third_party_invites.retain(|_, invite| invite.event_id() != Some(redacts));
         }
     }
 
@@ -296,6 +376,14 @@ impl BaseRoomInfo {
 
         self.notable_tags = notable_tags;
     }
+
+    /// Update the order of the room in each of its tags.
+    pub fn handle_tag_orders(&mut self, tags: &Tags) {
+        self.tag_orders = tags
+            .iter()
+            .filter_map(|(tag_name, tag_info)| Some((tag_name.clone(), tag_info.order?)))
+            .collect();
+    }
 }
 
 bitflags! {
@@ -316,6 +404,8 @@ bitflags! {
 
 trait OptionExt {
     fn has_event_id(&self, ev_id: &EventId) -> bool;
+
+    fn has_same_event(&self, other: &Self) -> bool;
 }
 
 impl<C> OptionExt for Option<MinimalStateEvent<C>>
@@ -326,6 +416,28 @@ where
     fn has_event_id(&self, ev_id: &EventId) -> bool {
         self.as_ref().is_some_and(|ev| ev.event_id() == Some(ev_id))
     }
+
+    fn has_same_event(&self, other: &Self) -> bool {
+        match (self, other) {
+            (None, None) => true,
+            (Some(a), Some(b)) => a.is_same_event(b),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the two maps contain the same events for the same keys.
+fn are_same_events<K, C>(
+    a: &BTreeMap<K, MinimalStateEvent<C>>,
+    b: &BTreeMap<K, MinimalStateEvent<C>>,
+) -> bool
+where
+    K: Eq,
+    C: StaticStateEventContent + RedactContent,
+    C::Redacted: RedactedStateEventContent,
+{
+    a.len() == b.len()
+        && a.iter().zip(b).all(|((key_a, a), (key_b, b))| key_a == key_b && a.is_same_event(b))
 }
 
 impl Default for BaseRoomInfo {
@@ -344,8 +456,10 @@ impl Default for BaseRoomInfo {
             tombstone: None,
             topic: None,
             rtc_member: BTreeMap::new(),
+            third_party_invites: BTreeMap::new(),
             is_marked_unread: false,
             notable_tags: RoomNotableTags::empty(),
+            tag_orders: BTreeMap::new(),
         }
     }
 }
@@ -12,20 +12,16 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-#[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
-use std::sync::RwLock as SyncRwLock;
 use std::{
     collections::{BTreeMap, HashSet},
-    mem,
-    sync::{atomic::AtomicBool, Arc},
+    iter, mem,
+    sync::{atomic::AtomicBool, Arc, RwLock as SyncRwLock},
 };
 
 use bitflags::bitflags;
 use eyeball::{SharedObservable, Subscriber};
-#[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
+use futures_util::{future, Stream, StreamExt};
 use matrix_sdk_common::ring_buffer::RingBuffer;
-#[cfg(feature = "experimental-sliding-sync")]
-use ruma::events::AnySyncTimelineEvent;
 use ruma::{
     api::client::sync::sync_events::v3::RoomSummary as RumaSummary,
     events::{
@@ -37,20 +33,23 @@ use ruma::{
             encryption::RoomEncryptionEventContent,
             guest_access::GuestAccess,
             history_visibility::HistoryVisibility,
-            join_rules::JoinRule,
-            member::{MembershipState, RoomMemberEventContent},
+            join_rules::{AllowRule, JoinRule},
+            member::{MembershipState, RoomMemberEvent, RoomMemberEventContent},
+            power_levels::RoomPowerLevels,
             redaction::SyncRoomRedactionEvent,
             tombstone::RoomTombstoneEventContent,
         },
-        tag::Tags,
+        tag::{TagName, Tags},
         AnyRoomAccountDataEvent, AnyStrippedStateEvent, AnySyncStateEvent,
-        RoomAccountDataEventType,
+        RoomAccountDataEventType, StateEventType,
     },
     room::RoomType,
     serde::Raw,
     EventId, MxcUri, OwnedEventId, OwnedMxcUri, OwnedRoomAliasId, OwnedRoomId, OwnedUserId,
     RoomAliasId, RoomId, RoomVersionId, UserId,
 };
+#[cfg(feature = "experimental-sliding-sync")]
+use ruma::{events::AnySyncTimelineEvent, MilliSecondsSinceUnixEpoch};
 use serde::{Deserialize, Serialize};
 use tokio::sync::broadcast;
 use tracing::{debug, field::debug, info, instrument, warn};
@@ -62,7 +61,7 @@ use super::{
 #[cfg(feature = "experimental-sliding-sync")]
 use crate::latest_event::LatestEvent;
 use crate::{
-    deserialized_responses::MemberEvent,
+    deserialized_responses::{MemberEvent, RawAnySyncOrStrippedState},
     read_receipts::RoomReadReceipts,
     store::{DynStateStore, Result as StoreResult, StateStoreExt},
     sync::UnreadNotificationsCount,
@@ -83,6 +82,52 @@ pub struct RoomInfoUpdate {
     pub trigger_room_list_update: bool,
 }
 
+/// A read receipt that was received for a room.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReceiptUpdate {
+    /// The room the receipt was sent in.
+    pub room_id: OwnedRoomId,
+    /// The user that sent the receipt.
+    pub user_id: OwnedUserId,
+    /// The event the receipt points to.
+    pub event_id: OwnedEventId,
+    /// The type of the receipt.
+    pub receipt_type: ReceiptType,
+    /// The thread the receipt applies to.
+    pub thread: ReceiptThread,
+}
+
+/// A consolidated view of the information needed to render a room in a room
+/// list, see [`Room::summary_view`].
+#[derive(Debug, Clone)]
+pub struct RoomSummaryView {
+    /// The ID of the room.
+    pub room_id: OwnedRoomId,
+    /// The name of the room, from its `m.room.name` state event.
+    pub name: Option<String>,
+    /// The display name of the room, if it has been computed already.
+    pub cached_display_name: Option<DisplayName>,
+    /// The URL of the avatar of the room.
+    pub avatar_url: Option<OwnedMxcUri>,
+    /// The last event in the room, if one has been cached during sliding sync.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub latest_event: Option<LatestEvent>,
+    /// The unread notification counts of the room.
+    pub unread_notification_counts: UnreadNotificationsCount,
+    /// Whether the room is encrypted.
+    pub is_encrypted: bool,
+}
+
+/// The source of the avatar to render for a room, see [`Room::avatar_source`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoomAvatarSource {
+    /// The room has an explicit avatar, from its `m.room.avatar` state event.
+    Explicit(OwnedMxcUri),
+    /// The room has no avatar, these are the avatars of its heroes, that can be
+    /// used to render a montage.
+    Heroes(Vec<OwnedMxcUri>),
+}
+
 /// The underlying room data structure collecting state for joined, left and
 /// invited rooms.
 #[derive(Debug, Clone)]
@@ -104,11 +149,41 @@ pub struct Room {
     /// to disk but held in memory.
     #[cfg(all(feature = "e2e-encryption", feature = "experimental-sliding-sync"))]
     pub latest_encrypted_events: Arc<SyncRwLock<RingBuffer<Raw<AnySyncTimelineEvent>>>>,
+
+    /// Snapshots of the member list at a given point in time, keyed by the
+    /// `at` token they were requested with.
+    ///
+    /// These are only held in memory, and are distinct from the live member
+    /// list of the room. Only the most recently used snapshots are kept.
+    members_at: Arc<SyncRwLock<RingBuffer<(String, Vec<RoomMemberEvent>)>>>,
+
+    /// The latest power levels of this room that were saved in the store, if
+    /// they changed since this room was loaded.
+    ///
+    /// This is only used to notify the subscribers of
+    /// [`Room::subscribe_to_power_level_changes`].
+    power_levels: SharedObservable<Option<RoomPowerLevels>>,
+
+    /// The content of the latest `m.room.power_levels` event of this room that
+    /// was saved in the store, once it is known.
+    ///
+    /// This is only used to detect when the power levels change, without
+    /// reading them from the store every time.
+    power_levels_content: Arc<SyncRwLock<Option<serde_json::Value>>>,
+
+    /// The name set locally for this room with
+    /// [`BaseClient::set_room_name_local`], until an `m.room.name` event is
+    /// received.
+    ///
+    /// This is only kept in memory, it is never saved in the store.
+    ///
+    /// [`BaseClient::set_room_name_local`]: crate::BaseClient::set_room_name_local
+    local_name: Arc<SyncRwLock<Option<String>>>,
 }
 
 /// The room summary containing member counts and members that should be used to
 /// calculate the room display name.
-#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
 pub struct RoomSummary {
     /// The heroes of the room, members that can be used as a fallback for the
     /// room's display name or avatar if these haven't been set.
@@ -125,6 +200,18 @@ pub struct RoomSummary {
     pub(crate) invited_member_count: u64,
 }
 
+/// A pending third-party invite in a room, i.e. an invite sent to someone
+/// identified by a third-party identifier, like an email address.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThirdPartyInvite {
+    /// The token used to match the invite with the member event that
+    /// resolves it.
+    pub token: String,
+    /// A user-readable string which represents the invited user, for example a
+    /// truncated version of their email address.
+    pub display_name: String,
+}
+
 /// Information about a member considered to be a room hero.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct RoomHero {
@@ -170,6 +257,20 @@ impl From<&MembershipState> for RoomState {
     }
 }
 
+/// The number of rooms in each [`RoomState`], see
+/// [`BaseClient::room_counts`](crate::BaseClient::room_counts).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RoomStateCounts {
+    /// The number of rooms in the [`RoomState::Joined`] state.
+    pub joined: usize,
+    /// The number of rooms in the [`RoomState::Invited`] state.
+    pub invited: usize,
+    /// The number of rooms in the [`RoomState::Left`] state.
+    ///
+    /// This includes the rooms we have knocked on or have been banned from.
+    pub left: usize,
+}
+
 /// The number of heroes chosen to compute a room's name, if the room didn't
 /// have a name set by the users themselves.
 ///
@@ -185,6 +286,11 @@ impl Room {
     const MAX_ENCRYPTED_EVENTS: std::num::NonZeroUsize =
         unsafe { std::num::NonZeroUsize::new_unchecked(10) };
 
+    /// The number of member list snapshots kept by `members_at`.
+    // SAFETY: `new_unchecked` is safe because 5 is not zero.
+    const MAX_MEMBERS_AT: std::num::NonZeroUsize =
+        unsafe { std::num::NonZeroUsize::new_unchecked(5) };
+
     pub(crate) fn new(
         own_user_id: &UserId,
         store: Arc<DynStateStore>,
@@ -212,6 +318,10 @@ impl Room {
                 Self::MAX_ENCRYPTED_EVENTS,
             ))),
             roominfo_update_sender,
+            members_at: Arc::new(SyncRwLock::new(RingBuffer::new(Self::MAX_MEMBERS_AT))),
+            power_levels: SharedObservable::new(None),
+            power_levels_content: Default::default(),
+            local_name: Default::default(),
         }
     }
 
@@ -232,7 +342,7 @@ impl Room {
 
     /// Whether this room's [`RoomType`] is `m.space`.
     pub fn is_space(&self) -> bool {
-        self.inner.read().room_type().is_some_and(|t| *t == RoomType::Space)
+        self.inner.read().is_space()
     }
 
     /// Returns the room's type as defined in its creation event
@@ -241,6 +351,14 @@ impl Room {
         self.inner.read().room_type().map(ToOwned::to_owned)
     }
 
+    /// Returns the room's version as defined in its creation event
+    /// (`m.room.create`).
+    ///
+    /// Returns `None` if the creation event is unknown.
+    pub fn room_version(&self) -> Option<RoomVersionId> {
+        self.inner.read().room_version().cloned()
+    }
+
     /// Get the unread notification counts.
     pub fn unread_notification_counts(&self) -> UnreadNotificationsCount {
         self.inner.read().notification_counts
@@ -428,11 +546,39 @@ impl Room {
         matches!(self.join_rule(), JoinRule::Public)
     }
 
+    /// Get the user who invited us to this room, if it's an invite.
+    ///
+    /// See [`RoomInfo::inviter`].
+    pub fn inviter(&self) -> Option<OwnedUserId> {
+        self.inner.read().inviter()
+    }
+
+    /// Get our own membership in this room.
+    ///
+    /// See [`RoomInfo::own_membership`].
+    pub fn own_membership(&self) -> MembershipState {
+        self.inner.read().own_membership()
+    }
+
     /// Get the join rule policy of this room.
     pub fn join_rule(&self) -> JoinRule {
         self.inner.read().join_rule().clone()
     }
 
+    /// Whether users can knock on this room to request to join it.
+    ///
+    /// See [`RoomInfo::allows_knocking`].
+    pub fn allows_knocking(&self) -> bool {
+        self.inner.read().allows_knocking()
+    }
+
+    /// Get the rooms whose members are allowed to join this room.
+    ///
+    /// See [`RoomInfo::join_rule_allowed_rooms`].
+    pub fn join_rule_allowed_rooms(&self) -> Vec<OwnedRoomId> {
+        self.inner.read().join_rule_allowed_rooms()
+    }
+
     /// Get the maximum power level that this room contains.
     ///
     /// This is useful if one wishes to normalize the power levels, e.g. from
@@ -459,11 +605,24 @@ impl Room {
         self.inner.read().tombstone().cloned()
     }
 
+    /// Get the room this room replaces, if it was created by upgrading another
+    /// room.
+    ///
+    /// See [`RoomInfo::predecessor`].
+    pub fn predecessor(&self) -> Option<(OwnedRoomId, OwnedEventId)> {
+        self.inner.read().predecessor()
+    }
+
     /// Get the topic of the room.
     pub fn topic(&self) -> Option<String> {
         self.inner.read().topic().map(ToOwned::to_owned)
     }
 
+    /// Get the pending third-party invites of the room.
+    pub fn third_party_invites(&self) -> Vec<ThirdPartyInvite> {
+        self.inner.read().third_party_invites()
+    }
+
     /// Is there a non expired membership with application "m.call" and scope
     /// "m.room" in this room
     pub fn has_active_room_call(&self) -> bool {
@@ -509,8 +668,18 @@ impl Room {
         let summary = {
             let inner = self.inner.read();
 
-            if let Some(name) = inner.name() {
-                let name = name.trim().to_owned();
+            // A name set locally is displayed until the `m.room.name` event is received.
+            let local_name = self.local_name.read().unwrap().clone();
+
+            // A name that only contains whitespace is as good as no name, don't show an
+            // empty display name in this case.
+            if let Some(name) = local_name
+                .as_deref()
+                .or(inner.name())
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                let name = name.to_owned();
                 drop(inner); // drop the lock on `self.inner` to avoid deadlocking in `update_cache`.
                 return Ok(update_cache(DisplayName::Named(name)));
             }
@@ -621,6 +790,20 @@ impl Room {
         self.inner.read().latest_event.as_deref().cloned()
     }
 
+    /// Return the ID of the last event in this room, if one has been cached
+    /// during sliding sync, without cloning the event.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn latest_event_id(&self) -> Option<OwnedEventId> {
+        self.inner.read().latest_event.as_ref()?.event_id()
+    }
+
+    /// Return the timestamp of the last event in this room, if one has been
+    /// cached during sliding sync, without cloning the event.
+    #[cfg(feature = "experimental-sliding-sync")]
+    pub fn latest_event_timestamp(&self) -> Option<MilliSecondsSinceUnixEpoch> {
+        self.inner.read().latest_event.as_ref()?.origin_server_ts()
+    }
+
     /// Return the most recent few encrypted events. When the keys come through
     /// to decrypt these, the most recent relevant one will replace
     /// latest_event. (We can't tell which one is relevant until
@@ -709,11 +892,80 @@ impl Room {
         Ok(members)
     }
 
+    /// Get a consolidated view of the information needed to render this room
+    /// in a room list.
+    ///
+    /// All the information is read at once from the same `RoomInfo`.
+    pub fn summary_view(&self) -> RoomSummaryView {
+        let info = self.inner.read();
+
+        RoomSummaryView {
+            room_id: self.room_id.clone(),
+            name: info.name().map(ToOwned::to_owned),
+            cached_display_name: info.cached_display_name.clone(),
+            avatar_url: info.avatar_url().map(ToOwned::to_owned),
+            #[cfg(feature = "experimental-sliding-sync")]
+            latest_event: info.latest_event().cloned(),
+            unread_notification_counts: info.notification_counts,
+            is_encrypted: info.is_encrypted(),
+        }
+    }
+
+    /// Get the source of the avatar to render for this room.
+    ///
+    /// This is the avatar of the room if it has one, or the avatars of the
+    /// heroes of the room otherwise. Heroes without an avatar are skipped.
+    pub fn avatar_source(&self) -> RoomAvatarSource {
+        let info = self.inner.read();
+
+        match info.avatar_url() {
+            Some(url) => RoomAvatarSource::Explicit(url.to_owned()),
+            None => RoomAvatarSource::Heroes(
+                info.heroes().iter().filter_map(|hero| hero.avatar_url.clone()).collect(),
+            ),
+        }
+    }
+
     /// Get the heroes for this room.
     pub fn heroes(&self) -> Vec<RoomHero> {
         self.inner.read().heroes().to_vec()
     }
 
+    /// Get the member events of this room as they were at the given `at`
+    /// token.
+    ///
+    /// Returns `None` if no member list was received for this token with
+    /// [`BaseClient::receive_members_at`]. This snapshot doesn't reflect the
+    /// live member list of the room, use [`Room::members`] for that.
+    ///
+    /// Only a few of the most recently used snapshots are kept in memory, and
+    /// they are all dropped when the room is left, even if
+    /// [`StoreConfig::retain_left_rooms_state`] is set.
+    ///
+    /// [`BaseClient::receive_members_at`]: crate::BaseClient::receive_members_at
+    /// [`StoreConfig::retain_left_rooms_state`]: crate::store::StoreConfig::retain_left_rooms_state
+    pub fn members_at(&self, at: &str) -> Option<Vec<RoomMemberEvent>> {
+        let mut members_at = self.members_at.write().unwrap();
+        let index = members_at.iter().position(|(token, _)| token == at)?;
+
+        // Move the snapshot to the back, so it's the last one to be evicted.
+        let snapshot = members_at.remove(index)?;
+        let members = snapshot.1.clone();
+        members_at.push(snapshot);
+
+        Some(members)
+    }
+
+    /// Store the member events of this room as they were at the given `at`
+    /// token.
+    ///
+    /// The least recently used snapshot is evicted if there are too many.
+    pub(crate) fn set_members_at(&self, at: String, members: Vec<RoomMemberEvent>) {
+        let mut members_at = self.members_at.write().unwrap();
+        members_at.retain(|(token, _)| *token != at);
+        members_at.push((at, members));
+    }
+
     /// Get the list of `RoomMember`s that are considered to be joined members
     /// of this room.
     #[deprecated = "Use members with RoomMemberships::JOIN instead"]
@@ -749,16 +1001,56 @@ impl Room {
         self.inner.subscribe()
     }
 
+    /// Subscribe to changes of the room's display name.
+    ///
+    /// A new value is emitted every time the cached display name is
+    /// recomputed to something different, e.g. after the room name, the
+    /// canonical alias or the members of the room changed.
+    pub fn subscribe_to_display_name(&self) -> impl Stream<Item = DisplayName> {
+        let mut last = self.cached_display_name();
+
+        self.inner.subscribe().filter_map(move |info| {
+            let display_name = info.cached_display_name.filter(|name| last.as_ref() != Some(name));
+
+            if display_name.is_some() {
+                last.clone_from(&display_name);
+            }
+
+            future::ready(display_name)
+        })
+    }
+
     /// Clone the inner `RoomInfo`.
     pub fn clone_info(&self) -> RoomInfo {
         self.inner.get()
     }
 
+    /// Whether the inner `RoomInfo` is known to be identical to the given one,
+    /// see [`RoomInfo::is_same_as`].
+    pub(crate) fn has_same_info(&self, room_info: &RoomInfo) -> bool {
+        self.inner.read().is_same_as(room_info)
+    }
+
     /// Update the summary with given RoomInfo.
     ///
     /// This also triggers an update for room info observers if
     /// `trigger_room_list_update` is true.
     pub fn set_room_info(&self, room_info: RoomInfo, trigger_room_list_update: bool) {
+        // The member list snapshots aren't useful anymore once the room is left.
+        if room_info.state() == RoomState::Left {
+            self.members_at.write().unwrap().clear();
+        }
+
+        // The name set locally is replaced by the one of a new `m.room.name` event.
+        let is_same_name_event =
+            match (&self.inner.read().base_info.name, &room_info.base_info.name) {
+                (Some(previous), Some(name)) => previous.is_same_event(name),
+                (previous, name) => previous.is_none() && name.is_none(),
+            };
+        if !is_same_name_event {
+            self.local_name.write().unwrap().take();
+        }
+
         self.inner.set(room_info);
 
         // Ignore error if no receiver exists.
@@ -767,6 +1059,22 @@ impl Room {
             .send(RoomInfoUpdate { room_id: self.room_id.clone(), trigger_room_list_update });
     }
 
+    /// Display the given name for this room until an `m.room.name` event is
+    /// received, and get the new display name of the room.
+    ///
+    /// The name is only kept in memory, it is never saved in the store.
+    pub(crate) async fn set_local_name(&self, name: String) -> StoreResult<DisplayName> {
+        *self.local_name.write().unwrap() = Some(name);
+        let display_name = self.compute_display_name().await?;
+
+        // Ignore error if no receiver exists.
+        let _ = self
+            .roominfo_update_sender
+            .send(RoomInfoUpdate { room_id: self.room_id.clone(), trigger_room_list_update: true });
+
+        Ok(display_name)
+    }
+
     /// Get the `RoomMember` with the given `user_id`.
     ///
     /// Returns `None` if the member was never part of this room, otherwise
@@ -843,6 +1151,103 @@ impl Room {
         }
     }
 
+    /// Subscribe to the changes of the power levels of this room.
+    ///
+    /// A new value is emitted every time an `m.room.power_levels` event with a
+    /// different content than the previous one is saved in the store, e.g. to
+    /// enable or disable the actions that the user can take in the room.
+    pub fn subscribe_to_power_level_changes(&self) -> impl Stream<Item = RoomPowerLevels> {
+        self.power_levels.subscribe().filter_map(future::ready)
+    }
+
+    /// Whether the given content of an `m.room.power_levels` event is different
+    /// from the content of the latest one that was saved in the store.
+    ///
+    /// The content is only read from the store the first time it is needed,
+    /// then it is kept in memory.
+    pub(crate) async fn is_power_levels_content_changed(
+        &self,
+        content: &serde_json::Value,
+    ) -> bool {
+        let known_content = self.power_levels_content.read().unwrap().clone();
+
+        let previous_content = match known_content {
+            Some(known_content) => Some(known_content),
+            None => {
+                let stored_content = match self
+                    .store
+                    .get_state_event(self.room_id(), StateEventType::RoomPowerLevels, "")
+                    .await
+                {
+                    Ok(Some(RawAnySyncOrStrippedState::Sync(raw))) => raw.get_field("content"),
+                    Ok(Some(RawAnySyncOrStrippedState::Stripped(raw))) => raw.get_field("content"),
+                    Ok(None) => Ok(None),
+                    Err(error) => {
+                        warn!("Failed to load the power levels of the room: {error}");
+                        return true;
+                    }
+                };
+                let stored_content = stored_content.ok().flatten();
+
+                if let Some(stored_content) = &stored_content {
+                    self.power_levels_content
+                        .write()
+                        .unwrap()
+                        .get_or_insert_with(|| stored_content.clone());
+                }
+
+                stored_content
+            }
+        };
+
+        previous_content.as_ref() != Some(content)
+    }
+
+    /// Notify the subscribers of [`Room::subscribe_to_power_level_changes`]
+    /// that the power levels of this room changed, once the
+    /// `m.room.power_levels` event with the given content was saved in the
+    /// store.
+    pub(crate) fn set_power_levels(
+        &self,
+        content: serde_json::Value,
+        power_levels: RoomPowerLevels,
+    ) {
+        *self.power_levels_content.write().unwrap() = Some(content);
+        self.power_levels.set(Some(power_levels));
+    }
+
+    /// Subscribe to changes of the room's tags.
+    ///
+    /// The tags are loaded from the store every time the room info is updated,
+    /// and a new value is emitted if they are different from the previous
+    /// value, e.g. after the room was added to or removed from the favourites.
+    pub fn subscribe_to_tags(&self) -> impl Stream<Item = Tags> {
+        let room = self.clone();
+        let mut last: Option<Tags> = None;
+
+        self.inner
+            .subscribe()
+            .then(move |_| {
+                let room = room.clone();
+                async move { room.tags().await.ok().flatten().unwrap_or_default() }
+            })
+            .filter_map(move |tags| {
+                let changed = last.as_ref().map_or(true, |last| {
+                    last.len() != tags.len()
+                        || iter::zip(last, &tags).any(|((name_a, info_a), (name_b, info_b))| {
+                            name_a != name_b || info_a.order != info_b.order
+                        })
+                });
+
+                let tags = changed.then(|| {
+                    last = Some(tags.clone());
+                    tags
+                });
+
+                future::ready(tags)
+            })
+    }
+
     /// Check whether the room is marked as favourite.
     ///
     /// A room is considered favourite if it has received the `m.favourite` tag.
@@ -858,6 +1263,15 @@ impl Room {
         self.inner.read().base_info.notable_tags.contains(RoomNotableTags::LOW_PRIORITY)
     }
 
+    /// Get the order of the room in the given tag, used to sort the rooms with
+    /// that tag manually.
+    ///
+    /// Returns `None` if the room doesn't have the tag, or if the tag has no
+    /// order.
+    pub fn tag_order(&self, tag_name: &TagName) -> Option<f64> {
+        self.inner.read().tag_order(tag_name)
+    }
+
     /// Get the receipt as an `OwnedEventId` and `Receipt` tuple for the given
     /// `receipt_type`, `thread` and `user_id` in this room.
     pub async fn load_user_receipt(
@@ -946,6 +1360,16 @@ pub struct RoomInfo {
     /// filled at start when creating a room, or on every successful sync.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub(crate) cached_display_name: Option<DisplayName>,
+
+    /// The user who invited us to this room, from the sender of our own
+    /// stripped `m.room.member` event.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) inviter: Option<OwnedUserId>,
+
+    /// Our own membership in this room, from the latest `m.room.member` event
+    /// of our own user that we have seen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) own_membership: Option<MembershipState>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -966,6 +1390,56 @@ pub(crate) enum SyncInfo {
 }
 
 impl RoomInfo {
+    /// Whether this room info is known to be identical to `other`.
+    ///
+    /// This is cheaper than comparing the serialized infos, but state events
+    /// are compared by their ID, so this might return `false` for identical
+    /// infos built from events without an ID, like the ones of invited rooms.
+    pub(crate) fn is_same_as(&self, other: &Self) -> bool {
+        let Self {
+            room_id,
+            room_state,
+            notification_counts,
+            summary,
+            members_synced,
+            last_prev_batch,
+            sync_info,
+            encryption_state_synced,
+            #[cfg(feature = "experimental-sliding-sync")]
+            latest_event,
+            read_receipts,
+            base_info,
+            warned_about_unknown_room_version: _,
+            cached_display_name,
+            inviter,
+            own_membership,
+        } = self;
+
+        #[cfg(feature = "experimental-sliding-sync")]
+        let same_latest_event = match (latest_event, &other.latest_event) {
+            (None, None) => true,
+            (Some(a), Some(b)) => a.is_same_as(b),
+            _ => false,
+        };
+        #[cfg(not(feature = "experimental-sliding-sync"))]
+        let same_latest_event = true;
+
+        *room_id == other.room_id
+            && *room_state == other.room_state
+            && *notification_counts == other.notification_counts
+            && *summary == other.summary
+            && *members_synced == other.members_synced
+            && *last_prev_batch == other.last_prev_batch
+            && *sync_info == other.sync_info
+            && *encryption_state_synced == other.encryption_state_synced
+            && same_latest_event
+            && *read_receipts == other.read_receipts
+            && base_info.is_same_as(&other.base_info)
+            && *cached_display_name == other.cached_display_name
+            && *inviter == other.inviter
+            && *own_membership == other.own_membership
+    }
+
     #[doc(hidden)] // used by store tests, otherwise it would be pub(crate)
     pub fn new(room_id: &RoomId, room_state: RoomState) -> Self {
         Self {
@@ -983,6 +1457,39 @@ impl RoomInfo {
             base_info: Box::new(BaseRoomInfo::new()),
             warned_about_unknown_room_version: Arc::new(false.into()),
             cached_display_name: None,
+            inviter: None,
+            own_membership: None,
+        }
+    }
+
+    /// Get the user who invited us to this room, if it's an invite.
+    ///
+    /// This is the sender of our own `m.room.member` event in the stripped
+    /// state of the invite.
+    pub fn inviter(&self) -> Option<OwnedUserId> {
+        if self.room_state == RoomState::Invited {
+            self.inviter.clone()
+        } else {
+            None
+        }
+    }
+
+    /// Get our own membership in this room.
+    ///
+    /// This is more precise than [`RoomInfo::state`], e.g. it allows to know
+    /// if we were banned from a room, or if we knocked on it. It is computed
+    /// from our own `m.room.member` event, and falls back to the membership
+    /// matching the state of the room if that event is unknown or outdated.
+    pub fn own_membership(&self) -> MembershipState {
+        match &self.own_membership {
+            Some(membership) if RoomState::from(membership) == self.room_state => {
+                membership.clone()
+            }
+            _ => match self.room_state {
+                RoomState::Joined => MembershipState::Join,
+                RoomState::Invited => MembershipState::Invite,
+                RoomState::Left => MembershipState::Leave,
+            },
         }
     }
 
@@ -1279,6 +1786,11 @@ impl RoomInfo {
         }
     }
 
+    /// Whether this room's [`RoomType`] is `m.space`.
+    pub fn is_space(&self) -> bool {
+        self.room_type().is_some_and(|t| *t == RoomType::Space)
+    }
+
     /// Get the creator of this room.
     pub fn creator(&self) -> Option<&UserId> {
         match self.base_info.create.as_ref()? {
@@ -1287,6 +1799,20 @@ impl RoomInfo {
         }
     }
 
+    /// Get the room this room replaces, if it was created by upgrading another
+    /// room.
+    ///
+    /// Returns the ID of the previous room and the ID of its last event, from
+    /// the `predecessor` field of the `m.room.create` event.
+    pub fn predecessor(&self) -> Option<(OwnedRoomId, OwnedEventId)> {
+        let predecessor = match self.base_info.create.as_ref()? {
+            MinimalStateEvent::Original(ev) => ev.content.predecessor.as_ref()?,
+            MinimalStateEvent::Redacted(ev) => ev.content.predecessor.as_ref()?,
+        };
+
+        Some((predecessor.room_id.clone(), predecessor.event_id.clone()))
+    }
+
     fn guest_access(&self) -> &GuestAccess {
         match &self.base_info.guest_access {
             Some(MinimalStateEvent::Original(ev)) => &ev.content.guest_access,
@@ -1314,6 +1840,33 @@ impl RoomInfo {
         }
     }
 
+    /// Whether users can knock on this room to request to join it, i.e. if
+    /// its join rule is `knock` or `knock_restricted`.
+    pub fn allows_knocking(&self) -> bool {
+        matches!(self.join_rule(), JoinRule::Knock | JoinRule::KnockRestricted(_))
+    }
+
+    /// Get the rooms whose members are allowed to join this room.
+    ///
+    /// Returns the room IDs of the `allow` conditions of a restricted join
+    /// rule, or an empty list if the join rule of the room is not restricted.
+    pub fn join_rule_allowed_rooms(&self) -> Vec<OwnedRoomId> {
+        let (JoinRule::Restricted(restricted) | JoinRule::KnockRestricted(restricted)) =
+            self.join_rule()
+        else {
+            return Vec::new();
+        };
+
+        restricted
+            .allow
+            .iter()
+            .filter_map(|rule| match rule {
+                AllowRule::RoomMembership(membership) => Some(membership.room_id.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// Get the name of this room.
     pub fn name(&self) -> Option<&str> {
         let name = &self.base_info.name.as_ref()?.as_original()?.content.name;
@@ -1329,6 +1882,27 @@ impl RoomInfo {
         Some(&self.base_info.topic.as_ref()?.as_original()?.content.topic)
     }
 
+    /// Returns the order of the room in the given tag, if any.
+    pub fn tag_order(&self, tag_name: &TagName) -> Option<f64> {
+        self.base_info.tag_orders.get(tag_name).copied()
+    }
+
+    /// Returns the pending third-party invites of this room.
+    ///
+    /// Redacted invites are ignored.
+    pub fn third_party_invites(&self) -> Vec<ThirdPartyInvite> {
+        self.base_info
+            .third_party_invites
+            .iter()
+            .filter_map(|(token, event)| {
+                Some(ThirdPartyInvite {
+                    token: token.clone(),
+                    display_name: event.as_original()?.content.display_name.clone(),
+                })
+            })
+            .collect()
+    }
+
     /// Get a list of all the valid (non expired) matrixRTC memberships and
     /// associated UserId's in this room.
     ///
@@ -1517,16 +2091,23 @@ fn compute_display_name_from_heroes(
 mod tests {
     use std::{
         ops::{Not, Sub},
+        pin::pin,
         str::FromStr,
         sync::Arc,
     };
 
     use assign::assign;
+    use futures_util::{FutureExt, StreamExt};
     #[cfg(feature = "experimental-sliding-sync")]
     use matrix_sdk_common::deserialized_responses::SyncTimelineEvent;
-    use matrix_sdk_test::{async_test, ALICE, BOB, CAROL};
+    use matrix_sdk_test::{
+        async_test, sync_timeline_event, InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder,
+        RoomAccountDataTestEvent, StateTestEvent, StrippedStateTestEvent, SyncResponseBuilder,
+        ALICE, BOB, CAROL,
+    };
     use ruma::{
         api::client::sync::sync_events::v3::RoomSummary as RumaSummary,
+        event_id,
         events::{
             call::member::{
                 Application, CallApplicationContent, CallMemberEventContent, Focus,
@@ -1536,21 +2117,23 @@ mod tests {
             room::{
                 canonical_alias::RoomCanonicalAliasEventContent,
                 encryption::{OriginalSyncRoomEncryptionEvent, RoomEncryptionEventContent},
+                history_visibility::HistoryVisibility,
                 member::{
                     MembershipState, RoomMemberEventContent, StrippedRoomMemberEvent,
                     SyncRoomMemberEvent,
                 },
-                name::RoomNameEventContent,
+                name::{RedactedRoomNameEventContent, RoomNameEventContent},
             },
+            tag::TagName,
             AnySyncStateEvent, EmptyStateKey, StateEventType, StateUnsigned, SyncStateEvent,
         },
-        room_alias_id, room_id,
+        int, owned_event_id, room_alias_id, room_id,
         serde::Raw,
         user_id, EventEncryptionAlgorithm, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedUserId,
-        UserId,
+        RoomVersionId, UserId,
     };
     use serde_json::json;
-    use stream_assert::{assert_pending, assert_ready};
+    use stream_assert::{assert_next_eq, assert_pending, assert_ready};
     use web_time::{Duration, SystemTime};
 
     #[cfg(feature = "experimental-sliding-sync")]
@@ -1560,7 +2143,9 @@ mod tests {
     use crate::latest_event::LatestEvent;
     use crate::{
         store::{MemoryStore, StateChanges, StateStore},
-        BaseClient, DisplayName, MinimalStateEvent, OriginalMinimalStateEvent, SessionMeta,
+        test_utils::{logged_in_base_client, receive_joined_room},
+        BaseClient, DisplayName, MinimalStateEvent, OriginalMinimalStateEvent,
+        RedactedMinimalStateEvent, SessionMeta,
     };
 
     #[test]
@@ -1601,6 +2186,8 @@ mod tests {
             read_receipts: Default::default(),
             warned_about_unknown_room_version: Arc::new(false.into()),
             cached_display_name: None,
+            inviter: None,
+            own_membership: None,
         };
 
         let info_json = json!({
@@ -1745,6 +2332,37 @@ mod tests {
         )
     }
 
+    #[test]
+    fn test_room_info_is_same_as() {
+        let info = RoomInfo::new(room_id!("!r:example.org"), RoomState::Joined);
+        assert!(info.is_same_as(&info.clone()));
+
+        let mut named = info.clone();
+        named.base_info.name = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+            content: RoomNameEventContent::new("Test Room".to_owned()),
+            event_id: Some(owned_event_id!("$name")),
+        }));
+        assert!(!info.is_same_as(&named));
+        assert!(named.is_same_as(&named.clone()));
+
+        // The same event, once redacted, isn't the same.
+        let mut redacted = named.clone();
+        redacted.base_info.name = Some(MinimalStateEvent::Redacted(RedactedMinimalStateEvent {
+            content: RedactedRoomNameEventContent::new(),
+            event_id: Some(owned_event_id!("$name")),
+        }));
+        assert!(!named.is_same_as(&redacted));
+
+        // Events without an ID are never considered to be the same.
+        let mut stripped = info.clone();
+        stripped.base_info.name = Some(make_name_event());
+        assert!(!stripped.is_same_as(&stripped.clone()));
+
+        let mut counts = info.clone();
+        counts.notification_counts.highlight_count = 1;
+        assert!(!info.is_same_as(&counts));
+    }
+
     #[async_test]
     async fn test_is_favourite() {
         // Given a room,
@@ -1819,6 +2437,193 @@ mod tests {
         assert!(room.is_favourite().not());
     }
 
+    #[test]
+    fn test_is_space() {
+        let create_event = |content: serde_json::Value| -> AnySyncStateEvent {
+            serde_json::from_value(json!({
+                "content": content,
+                "event_id": "$create:example.org",
+                "origin_server_ts": 151800140,
+                "sender": "@alice:example.org",
+                "state_key": "",
+                "type": "m.room.create",
+            }))
+            .unwrap()
+        };
+
+        let mut space_info = RoomInfo::new(room_id!("!space:example.org"), RoomState::Joined);
+        assert!(!space_info.is_space());
+        space_info.handle_state_event(&create_event(json!({
+            "room_version": "10",
+            "type": "m.space",
+        })));
+        assert!(space_info.is_space());
+
+        let mut room_info = RoomInfo::new(room_id!("!room:example.org"), RoomState::Joined);
+        room_info.handle_state_event(&create_event(json!({ "room_version": "10" })));
+        assert!(!room_info.is_space());
+    }
+
+    #[test]
+    fn test_predecessor() {
+        let mut info = RoomInfo::new(room_id!("!new:example.org"), RoomState::Joined);
+        assert_eq!(info.predecessor(), None);
+
+        let create_event: AnySyncStateEvent = serde_json::from_value(json!({
+            "content": {
+                "room_version": "10",
+                "predecessor": {
+                    "room_id": "!old:example.org",
+                    "event_id": "$tombstone:example.org",
+                },
+            },
+            "event_id": "$create:example.org",
+            "origin_server_ts": 151800140,
+            "sender": "@alice:example.org",
+            "state_key": "",
+            "type": "m.room.create",
+        }))
+        .unwrap();
+        info.handle_state_event(&create_event);
+
+        assert_eq!(
+            info.predecessor(),
+            Some((
+                room_id!("!old:example.org").to_owned(),
+                event_id!("$tombstone:example.org").to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_allows_knocking() {
+        let join_rules_event = |join_rule: &str| -> AnySyncStateEvent {
+            serde_json::from_value(json!({
+                "content": { "join_rule": join_rule },
+                "event_id": "$join_rules:example.org",
+                "origin_server_ts": 151800140,
+                "sender": "@alice:example.org",
+                "state_key": "",
+                "type": "m.room.join_rules",
+            }))
+            .unwrap()
+        };
+
+        let mut info = RoomInfo::new(room_id!("!room:example.org"), RoomState::Joined);
+        info.handle_state_event(&join_rules_event("knock"));
+        assert!(info.allows_knocking());
+
+        info.handle_state_event(&join_rules_event("invite"));
+        assert!(!info.allows_knocking());
+    }
+
+    #[test]
+    fn test_join_rule_allowed_rooms() {
+        let mut info = RoomInfo::new(room_id!("!room:example.org"), RoomState::Joined);
+        assert!(info.join_rule_allowed_rooms().is_empty());
+
+        let join_rules_event: AnySyncStateEvent = serde_json::from_value(json!({
+            "content": {
+                "join_rule": "restricted",
+                "allow": [
+                    { "type": "m.room_membership", "room_id": "!space:example.org" },
+                    { "type": "m.room_membership", "room_id": "!other_space:example.org" },
+                ],
+            },
+            "event_id": "$join_rules:example.org",
+            "origin_server_ts": 151800140,
+            "sender": "@alice:example.org",
+            "state_key": "",
+            "type": "m.room.join_rules",
+        }))
+        .unwrap();
+        info.handle_state_event(&join_rules_event);
+
+        assert_eq!(
+            info.join_rule_allowed_rooms(),
+            [
+                room_id!("!space:example.org").to_owned(),
+                room_id!("!other_space:example.org").to_owned()
+            ]
+        );
+    }
+
+    #[async_test]
+    async fn test_tag_order() {
+        // Given a room,
+        let client = BaseClient::new();
+
+        client
+            .set_session_meta(
+                SessionMeta {
+                    user_id: user_id!("@alice:example.org").into(),
+                    device_id: ruma::device_id!("AYEAYEAYE").into(),
+                },
+                #[cfg(feature = "e2e-encryption")]
+                None,
+            )
+            .await
+            .unwrap();
+
+        let room_id = room_id!("!test:localhost");
+        let room = client.get_or_create_room(room_id, RoomState::Joined);
+
+        assert_eq!(room.tag_order(&TagName::Favorite), None);
+
+        // Subscribe to the `RoomInfo`.
+        let mut room_info_subscriber = room.subscribe_info();
+
+        assert_pending!(room_info_subscriber);
+
+        // When a tag with an order is handled and applied,
+        let tag_raw = Raw::new(&json!({
+            "content": {
+                "tags": {
+                    "m.favourite": {
+                        "order": 0.25
+                    },
+                    "u.work": {},
+                },
+            },
+            "type": "m.tag",
+        }))
+        .unwrap()
+        .cast();
+
+        let mut changes = StateChanges::default();
+        client.handle_room_account_data(room_id, &[tag_raw], &mut changes).await;
+        client.apply_changes(&changes, false);
+
+        // Then the `RoomInfo` is getting notified,
+        assert_ready!(room_info_subscriber);
+        assert_pending!(room_info_subscriber);
+
+        // And the order is exposed.
+        assert_eq!(room.tag_order(&TagName::Favorite), Some(0.25));
+        assert_eq!(room.tag_order(&TagName::from("u.work")), None);
+
+        // When the order changes,
+        let tag_raw = Raw::new(&json!({
+            "content": {
+                "tags": {
+                    "m.favourite": {
+                        "order": 0.75
+                    },
+                },
+            },
+            "type": "m.tag",
+        }))
+        .unwrap()
+        .cast();
+        client.handle_room_account_data(room_id, &[tag_raw], &mut changes).await;
+        client.apply_changes(&changes, false);
+
+        // Then the `RoomInfo` is getting notified with the new order.
+        assert_ready!(room_info_subscriber);
+        assert_pending!(room_info_subscriber);
+        assert_eq!(room.tag_order(&TagName::Favorite), Some(0.75));
+    }
+
     #[async_test]
     async fn test_is_low_priority() {
         // Given a room,
@@ -1930,6 +2735,31 @@ mod tests {
         Raw::new(&ev_json).unwrap().cast()
     }
 
+    #[test]
+    fn test_members_at_keeps_the_most_recently_used_snapshots() {
+        let (_, room) = make_room_test_helper(RoomState::Joined);
+
+        for i in 0..Room::MAX_MEMBERS_AT.get() {
+            room.set_members_at(format!("t{i}"), Vec::new());
+        }
+
+        // Using the oldest snapshot makes it the most recent one,
+        assert!(room.members_at("t0").is_some());
+
+        // So the next oldest one is evicted by a new snapshot.
+        room.set_members_at("new".to_owned(), Vec::new());
+        assert!(room.members_at("t0").is_some());
+        assert!(room.members_at("t1").is_none());
+        assert!(room.members_at("new").is_some());
+
+        // All the snapshots are cleared once the room is left.
+        let mut room_info = room.clone_info();
+        room_info.mark_as_left();
+        room.set_room_info(room_info, false);
+        assert!(room.members_at("t0").is_none());
+        assert!(room.members_at("new").is_none());
+    }
+
     #[async_test]
     async fn test_display_name_for_joined_room_is_empty_if_no_info() {
         let (_, room) = make_room_test_helper(RoomState::Joined);
@@ -2053,6 +2883,62 @@ mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_display_name_falls_back_to_heroes_if_room_name_empty() {
+        let (store, room) = make_room_test_helper(RoomState::Joined);
+        let room_id = room_id!("!test:localhost");
+        let matthew = user_id!("@matthew:example.org");
+        let me = user_id!("@me:example.org");
+        let mut changes = StateChanges::new("".to_owned());
+        let summary = assign!(RumaSummary::new(), {
+            joined_member_count: Some(2u32.into()),
+            heroes: vec![me.to_owned(), matthew.to_owned()],
+        });
+
+        let members = changes
+            .state
+            .entry(room_id.to_owned())
+            .or_default()
+            .entry(StateEventType::RoomMember)
+            .or_default();
+        members.insert(matthew.into(), make_member_event(matthew, "Matthew").cast());
+        members.insert(me.into(), make_member_event(me, "Me").cast());
+
+        store.save_changes(&changes).await.unwrap();
+
+        room.inner.update_if(|info| info.update_from_ruma_summary(&summary));
+        room.inner.update(|info| info.base_info.name = Some(make_name_event()));
+        assert_eq!(
+            room.compute_display_name().await.unwrap(),
+            DisplayName::Named("Test Room".to_owned())
+        );
+
+        // The name is removed by setting it to an empty string.
+        room.inner.update(|info| {
+            info.base_info.name = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+                content: RoomNameEventContent::new(String::new()),
+                event_id: None,
+            }))
+        });
+        assert_eq!(room.name(), None);
+        assert_eq!(
+            room.compute_display_name().await.unwrap(),
+            DisplayName::Calculated("Matthew".to_owned())
+        );
+
+        // A name with only whitespace is ignored too.
+        room.inner.update(|info| {
+            info.base_info.name = Some(MinimalStateEvent::Original(OriginalMinimalStateEvent {
+                content: RoomNameEventContent::new("  ".to_owned()),
+                event_id: None,
+            }))
+        });
+        assert_eq!(
+            room.compute_display_name().await.unwrap(),
+            DisplayName::Calculated("Matthew".to_owned())
+        );
+    }
+
     #[async_test]
     async fn test_display_name_dm_invited_no_heroes() {
         let (store, room) = make_room_test_helper(RoomState::Invited);
@@ -2572,4 +3458,421 @@ mod tests {
         assert!(room.is_encryption_state_synced());
         assert!(room.is_encrypted());
     }
+
+    #[async_test]
+    async fn test_inviter() {
+        let user_id = user_id!("@alice:example.org");
+        let inviter_user_id = user_id!("@bob:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let response = sync_builder
+            .add_invited_room(
+                InvitedRoomBuilder::new(room_id)
+                    .add_state_event(StrippedStateTestEvent::Custom(json!({
+                        "content": { "membership": "join" },
+                        "sender": "@carol:example.org",
+                        "state_key": "@carol:example.org",
+                        "type": "m.room.member",
+                    })))
+                    .add_state_event(StrippedStateTestEvent::Custom(json!({
+                        "content": { "membership": "invite" },
+                        "sender": inviter_user_id,
+                        "state_key": user_id,
+                        "type": "m.room.member",
+                    }))),
+            )
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        let room = client.get_room(room_id).unwrap();
+        assert_eq!(room.state(), RoomState::Invited);
+        assert_eq!(room.inviter().as_deref(), Some(inviter_user_id));
+
+        // Once the room is joined, there is no inviter anymore.
+        receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+        assert_eq!(room.state(), RoomState::Joined);
+        assert_eq!(room.inviter(), None);
+    }
+
+    #[async_test]
+    async fn test_own_membership() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room =
+            receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+        assert_eq!(room.own_membership(), MembershipState::Join);
+
+        // We get banned from the room.
+        let response = sync_builder
+            .add_left_room(LeftRoomBuilder::new(room_id).add_timeline_event(sync_timeline_event!({
+                "content": {
+                    "membership": "ban",
+                    "reason": "Spamming",
+                },
+                "event_id": "$ban",
+                "origin_server_ts": 151800140,
+                "sender": "@mod:example.org",
+                "state_key": user_id,
+                "type": "m.room.member",
+            })))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(room.state(), RoomState::Left);
+        assert_eq!(room.own_membership(), MembershipState::Ban);
+    }
+
+    #[async_test]
+    async fn test_history_visibility_updates_across_syncs() {
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(None).await;
+        let mut roominfo_updates = client.roominfo_update_receiver();
+
+        // When the room history is only shared with members,
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room = receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "history_visibility": "shared"
+                },
+                "event_id": "$history_visibility_1:example.org",
+                "origin_server_ts": 151957878,
+                "sender": "@example:example.org",
+                "state_key": "",
+                "type": "m.room.history_visibility",
+            }))),
+        )
+        .await;
+        assert_eq!(room.history_visibility(), HistoryVisibility::Shared);
+        assert_eq!(roominfo_updates.recv().await.unwrap().room_id, room_id);
+
+        // And it becomes world readable in a later sync,
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::HistoryVisibility),
+        )
+        .await;
+
+        // Then the new history visibility is exposed, and observers are notified.
+        assert_eq!(room.history_visibility(), HistoryVisibility::WorldReadable);
+        assert_eq!(roominfo_updates.recv().await.unwrap().room_id, room_id);
+    }
+
+    #[async_test]
+    async fn test_third_party_invites() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!test:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        // When a third-party invite is sent,
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room = receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "display_name": "bob@ex...",
+                    "key_validity_url": "https://identity.example.org/_matrix/identity/v2/pubkey/isvalid",
+                    "public_key": "Zm9vYmFy",
+                },
+                "event_id": "$third_party_invite:example.org",
+                "origin_server_ts": 151957878,
+                "sender": user_id,
+                "state_key": "the_token",
+                "type": "m.room.third_party_invite",
+            }))),
+        )
+        .await;
+
+        // Then it's pending,
+        let invites = room.third_party_invites();
+        assert_eq!(invites.len(), 1);
+        assert_eq!(invites[0].token, "the_token");
+        assert_eq!(invites[0].display_name, "bob@ex...");
+
+        // Until the invited user is bound to it.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "membership": "invite",
+                    "third_party_invite": {
+                        "display_name": "bob@ex...",
+                        "signed": {
+                            "mxid": "@bob:example.org",
+                            "signatures": {},
+                            "token": "the_token",
+                        },
+                    },
+                },
+                "event_id": "$member:example.org",
+                "origin_server_ts": 151957879,
+                "sender": user_id,
+                "state_key": "@bob:example.org",
+                "type": "m.room.member",
+            }))),
+        )
+        .await;
+
+        assert!(room.third_party_invites().is_empty());
+    }
+
+    #[async_test]
+    async fn test_heroes_from_summary() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let room = receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id).set_room_summary(json!({
+                "m.heroes": ["@bob:example.org", "@carol:example.org"],
+                "m.joined_member_count": 3,
+            })),
+        )
+        .await;
+
+        let heroes = room.heroes();
+        assert_eq!(
+            heroes.iter().map(|hero| hero.user_id.as_str()).collect::<Vec<_>>(),
+            ["@bob:example.org", "@carol:example.org"]
+        );
+        assert!(heroes.iter().all(|hero| hero.display_name.is_none() && hero.avatar_url.is_none()));
+        assert_eq!(room.clone_info().heroes(), heroes.as_slice());
+    }
+
+    #[async_test]
+    async fn test_cached_display_name() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        // The display name isn't computed when the room is created.
+        let room = client.get_or_create_room(room_id, RoomState::Joined);
+        assert!(room.cached_display_name().is_none());
+
+        // It is computed after a sync containing the room.
+        receive_joined_room(
+            &client,
+            &mut SyncResponseBuilder::new(),
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+        )
+        .await;
+
+        assert_eq!(room.cached_display_name(), Some(DisplayName::Named("room name".to_owned())));
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_display_name() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room = receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomName),
+        )
+        .await;
+
+        let mut display_names = pin!(room.subscribe_to_display_name());
+        assert_pending!(display_names);
+
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": { "name": "Renamed room" },
+                "event_id": "$renamed",
+                "origin_server_ts": 151800140,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.name",
+            }))),
+        )
+        .await;
+
+        assert_next_eq!(display_names, DisplayName::Named("Renamed room".to_owned()));
+        assert_pending!(display_names);
+    }
+
+    #[async_test]
+    async fn test_room_version() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        // The create event is unknown.
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room =
+            receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+        assert_eq!(room.room_version(), None);
+
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": {
+                    "creator": "@example:localhost",
+                    "room_version": "11",
+                },
+                "event_id": "$create",
+                "origin_server_ts": 151800140,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.create",
+            }))),
+        )
+        .await;
+
+        assert_eq!(room.room_version(), Some(RoomVersionId::V11));
+    }
+
+    fn tags_event(tags: serde_json::Value) -> RoomAccountDataTestEvent {
+        RoomAccountDataTestEvent::Custom(json!({
+            "content": { "tags": tags },
+            "type": "m.tag",
+        }))
+    }
+
+    #[async_test]
+    async fn test_tag_order_change_updates_room_info() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+        let mut room_info_updates = client.roominfo_update_receiver();
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room = receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id)
+                .add_account_data(tags_event(json!({ "m.favourite": { "order": 0.5 } }))),
+        )
+        .await;
+
+        assert!(room.is_favourite());
+        assert_eq!(room.tag_order(&TagName::Favorite), Some(0.5));
+        assert_eq!(room_info_updates.try_recv().unwrap().room_id, room_id);
+
+        // Only the order of the tag changes.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id)
+                .add_account_data(tags_event(json!({ "m.favourite": { "order": 0.1 } }))),
+        )
+        .await;
+
+        assert!(room.is_favourite());
+        assert_eq!(room.tag_order(&TagName::Favorite), Some(0.1));
+        assert_eq!(room_info_updates.try_recv().unwrap().room_id, room_id);
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_tags() {
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room =
+            receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+
+        let mut tags = pin!(room.subscribe_to_tags());
+        assert_pending!(tags);
+
+        // The room is added to the favourites.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id)
+                .add_account_data(tags_event(json!({ "m.favourite": { "order": 0.5 } }))),
+        )
+        .await;
+
+        let current = tags.next().now_or_never().unwrap().unwrap();
+        assert_eq!(current.keys().collect::<Vec<_>>(), [&TagName::Favorite]);
+        assert_eq!(current[&TagName::Favorite].order, Some(0.5));
+        assert_pending!(tags);
+
+        // The room is removed from the favourites.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_account_data(tags_event(json!({}))),
+        )
+        .await;
+
+        let current = tags.next().now_or_never().unwrap().unwrap();
+        assert!(current.is_empty());
+        assert_pending!(tags);
+    }
+
+    #[async_test]
+    async fn test_subscribe_to_power_level_changes() {
+        let user_id = user_id!("@alice:example.org");
+        let room_id = room_id!("!ithpyNKDtmhneaTQja:example.org");
+        let client = logged_in_base_client(Some(user_id)).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        let room =
+            receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(room_id)).await;
+
+        let mut power_level_changes = pin!(room.subscribe_to_power_level_changes());
+        assert_pending!(power_level_changes);
+
+        // Other state events don't trigger an update.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::RoomTopic),
+        )
+        .await;
+        assert_pending!(power_level_changes);
+
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::PowerLevels),
+        )
+        .await;
+
+        let power_levels = power_level_changes.next().now_or_never().flatten().unwrap();
+        assert_eq!(power_levels.for_user(user_id!("@example:localhost")), int!(100));
+        assert_pending!(power_level_changes);
+
+        // Receiving the same power levels again doesn't trigger an update.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::PowerLevels),
+        )
+        .await;
+        assert_pending!(power_level_changes);
+
+        // Different power levels trigger an update.
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Custom(json!({
+                "content": { "users": { user_id: 50 } },
+                "event_id": "$power_levels_2",
+                "origin_server_ts": 151393756,
+                "sender": "@example:localhost",
+                "state_key": "",
+                "type": "m.room.power_levels",
+            }))),
+        )
+        .await;
+
+        let power_levels = power_level_changes.next().now_or_never().flatten().unwrap();
+        assert_eq!(power_levels.for_user(user_id), int!(50));
+        assert_pending!(power_level_changes);
+    }
 }
@@ -95,7 +95,7 @@ impl BaseClient {
             .await?;
 
         trace!("ready to submit changes to store");
-        self.store.save_changes(&changes).await?;
+        self.save_changes(&changes, "process_sliding_sync_e2ee").await?;
         self.apply_changes(&changes, true);
         trace!("applied changes");
 
@@ -149,6 +149,8 @@ impl BaseClient {
             self.handle_account_data(&account_data.global, &mut changes).await;
         }
 
+        let push_rules_changed = self.push_rules_changed(&changes).await?;
+
         let mut new_rooms = RoomUpdates::default();
         let mut notifications = Default::default();
         let mut rooms_account_data = account_data.rooms.clone();
@@ -210,6 +212,13 @@ impl BaseClient {
         }
 
         for (room_id, raw) in &extensions.typing.rooms {
+            match raw.deserialize() {
+                Ok(event) => changes.add_typing(room_id, event.content.user_ids),
+                Err(e) => {
+                    warn!(?room_id, "Failed to deserialize typing room event: {e}");
+                }
+            }
+
             // We assume this can only happen in joined rooms, or something's very wrong.
             new_rooms
                 .join
@@ -290,8 +299,10 @@ impl BaseClient {
 
         changes.ambiguity_maps = ambiguity_cache.cache;
 
+        self.handle_power_levels_changes(&mut changes).await;
+
         trace!("ready to submit changes to store");
-        store.save_changes(&changes).await?;
+        self.save_changes(&changes, "process_sliding_sync").await?;
         self.apply_changes(&changes, false);
         trace!("applied changes");
 
@@ -309,6 +320,7 @@ impl BaseClient {
             presence: Default::default(),
             account_data: account_data.global.clone(),
             to_device: Default::default(),
+            push_rules_changed,
         })
     }
 
@@ -384,6 +396,8 @@ impl BaseClient {
                 changes,
                 notifications,
                 ambiguity_cache,
+                #[cfg(feature = "e2e-encryption")]
+                &mut Default::default(),
             )
             .await?;
 
@@ -757,14 +771,15 @@ mod tests {
         },
         mxc_uri, owned_mxc_uri, owned_user_id, room_alias_id, room_id,
         serde::Raw,
-        uint, user_id, JsOption, MxcUri, OwnedRoomId, OwnedUserId, RoomAliasId, RoomId, UserId,
+        uint, user_id, JsOption, MilliSecondsSinceUnixEpoch, MxcUri, OwnedRoomId, OwnedUserId,
+        RoomAliasId, RoomId, UserId,
     };
     use serde_json::json;
 
     use super::cache_latest_events;
     use crate::{
         rooms::normal::RoomHero, store::MemoryStore, test_utils::logged_in_base_client, BaseClient,
-        Room, RoomState,
+        Room, RoomAvatarSource, RoomState,
     };
 
     #[async_test]
@@ -1392,6 +1407,54 @@ mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_room_avatar_source_from_heroes() {
+        // Given a logged-in client
+        let client = logged_in_base_client(None).await;
+        let room_id = room_id!("!r:e.uk");
+        assert_eq!(client.room_avatar_source(room_id), None);
+
+        // When I send sliding sync response containing a room without an avatar, but
+        // with heroes
+        let mut room = v4::SlidingSyncRoom::new();
+        room.heroes = Some(vec![
+            assign!(v4::SlidingSyncRoomHero::new(owned_user_id!("@gordon:e.uk")), {
+                name: Some("Gordon".to_owned()),
+                avatar: Some(owned_mxc_uri!("mxc://e.uk/med1"))
+            }),
+            assign!(v4::SlidingSyncRoomHero::new(owned_user_id!("@barney:e.uk")), {
+                name: Some("Barney".to_owned()),
+            }),
+            assign!(v4::SlidingSyncRoomHero::new(owned_user_id!("@alice:e.uk")), {
+                name: Some("Alice".to_owned()),
+                avatar: Some(owned_mxc_uri!("mxc://e.uk/med2"))
+            }),
+        ]);
+        let response = response_with_room(room_id, room);
+        client.process_sliding_sync(&response, &()).await.expect("Failed to process sync");
+
+        // Then the avatars of the heroes are used
+        assert_eq!(
+            client.room_avatar_source(room_id),
+            Some(RoomAvatarSource::Heroes(vec![
+                owned_mxc_uri!("mxc://e.uk/med1"),
+                owned_mxc_uri!("mxc://e.uk/med2"),
+            ]))
+        );
+
+        // When the room gets an avatar
+        let mut room = v4::SlidingSyncRoom::new();
+        room.avatar = JsOption::Some(owned_mxc_uri!("mxc://e.uk/room"));
+        let response = response_with_room(room_id, room);
+        client.process_sliding_sync(&response, &()).await.expect("Failed to process sync");
+
+        // Then it is used instead
+        assert_eq!(
+            client.room_avatar_source(room_id),
+            Some(RoomAvatarSource::Explicit(owned_mxc_uri!("mxc://e.uk/room")))
+        );
+    }
+
     #[async_test]
     async fn test_last_event_from_sliding_sync_is_cached() {
         // Given a logged-in client
@@ -1479,6 +1542,55 @@ mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_latest_event_id() {
+        // Given a logged-in client
+        let client = logged_in_base_client(None).await;
+        let room_id = room_id!("!r:e.uk");
+        assert_eq!(client.latest_event_id(room_id), None);
+
+        // When the sliding sync response contains a message
+        let event = json!({
+            "sender": "@alice:example.com",
+            "type": "m.room.message",
+            "event_id": "$ida",
+            "origin_server_ts": 12344446,
+            "content": { "body":"A", "msgtype": "m.text" },
+        });
+        let room = room_with_timeline(&[event]);
+        let response = response_with_room(room_id, room);
+        client.process_sliding_sync(&response, &()).await.expect("Failed to process sync");
+
+        // Then its ID is the latest event ID of the room
+        assert_eq!(client.latest_event_id(room_id).unwrap(), "$ida");
+    }
+
+    #[async_test]
+    async fn test_latest_event_timestamp() {
+        // Given a logged-in client
+        let client = logged_in_base_client(None).await;
+        let room_id = room_id!("!r:e.uk");
+        assert_eq!(client.latest_event_timestamp(room_id), None);
+
+        // When the sliding sync response contains a message
+        let event = json!({
+            "sender": "@alice:example.com",
+            "type": "m.room.message",
+            "event_id": "$ida",
+            "origin_server_ts": 12344446,
+            "content": { "body":"A", "msgtype": "m.text" },
+        });
+        let room = room_with_timeline(&[event]);
+        let response = response_with_room(room_id, room);
+        client.process_sliding_sync(&response, &()).await.expect("Failed to process sync");
+
+        // Then its timestamp is the latest event timestamp of the room
+        assert_eq!(
+            client.latest_event_timestamp(room_id),
+            Some(MilliSecondsSinceUnixEpoch(uint!(12344446)))
+        );
+    }
+
     #[async_test]
     async fn test_when_no_events_we_dont_cache_any() {
         let events = &[];
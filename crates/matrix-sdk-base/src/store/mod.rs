@@ -49,17 +49,23 @@ use ruma::{
     events::{
         presence::PresenceEvent,
         receipt::ReceiptEventContent,
-        room::{member::StrippedRoomMemberEvent, redaction::SyncRoomRedactionEvent},
+        room::{
+            member::{MembershipState, StrippedRoomMemberEvent},
+            power_levels::RoomPowerLevels,
+            redaction::SyncRoomRedactionEvent,
+        },
         AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, AnyStrippedStateEvent,
-        AnySyncStateEvent, GlobalAccountDataEventType, RoomAccountDataEventType, StateEventType,
+        AnySyncStateEvent, AnySyncTimelineEvent, GlobalAccountDataEventType,
+        RoomAccountDataEventType, StateEventType,
     },
     serde::Raw,
     EventId, OwnedEventId, OwnedRoomId, OwnedUserId, RoomId, UserId,
 };
-use tokio::sync::{broadcast, Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex, OwnedMutexGuard, RwLock};
 
 use crate::{
-    rooms::{normal::RoomInfoUpdate, RoomInfo, RoomState},
+    rooms::{normal::RoomInfoUpdate, RoomInfo, RoomState, RoomStateCounts},
     MinimalRoomMemberEvent, Room, RoomStateFilter, SessionMeta,
 };
 
@@ -145,6 +151,9 @@ pub(crate) struct Store {
     session_meta: Arc<OnceCell<SessionMeta>>,
     /// The current sync token that should be used for the next sync call.
     pub(super) sync_token: Arc<RwLock<Option<String>>>,
+    /// Whether a sync token was restored from the inner `StateStore` when the
+    /// session meta was set.
+    had_prior_sync: Arc<OnceCell<bool>>,
     /// All rooms the store knows about.
     rooms: Arc<StdRwLock<ObservableMap<OwnedRoomId, Room>>>,
     /// A lock to synchronize access to the store, such that data by the sync is
@@ -159,6 +168,7 @@ impl Store {
             inner,
             session_meta: Default::default(),
             sync_token: Default::default(),
+            had_prior_sync: Default::default(),
             rooms: Arc::new(StdRwLock::new(ObservableMap::new())),
             sync_lock: Default::default(),
         }
@@ -169,6 +179,12 @@ impl Store {
         &self.sync_lock
     }
 
+    /// Lock the syncing lock with a guard that can outlive the store, e.g. to
+    /// move it to another task.
+    pub(crate) async fn lock_sync_owned(&self) -> OwnedMutexGuard<()> {
+        self.sync_lock.clone().lock_owned().await
+    }
+
     /// Set the meta of the session.
     ///
     /// Restores the state of this `Store` from the given `SessionMeta` and the
@@ -200,6 +216,7 @@ impl Store {
 
         let token =
             self.get_kv_data(StateStoreDataKey::SyncToken).await?.and_then(|s| s.into_sync_token());
+        self.had_prior_sync.set(token.is_some()).expect("Session Meta was already set");
         *self.sync_token.write().await = token;
 
         self.session_meta.set(session_meta).expect("Session Meta was already set");
@@ -207,6 +224,14 @@ impl Store {
         Ok(())
     }
 
+    /// Whether a sync token was restored from the inner `StateStore` when the
+    /// session meta was set.
+    ///
+    /// Returns `false` if the session meta wasn't set yet.
+    pub fn had_prior_sync(&self) -> bool {
+        self.had_prior_sync.get().copied().unwrap_or(false)
+    }
+
     /// The current [`SessionMeta`] containing our user ID and device ID.
     pub fn session_meta(&self) -> Option<&SessionMeta> {
         self.session_meta.get()
@@ -217,6 +242,31 @@ impl Store {
         self.rooms.read().unwrap().iter().cloned().collect()
     }
 
+    /// Get a page of the rooms this store knows about.
+    pub fn rooms_paginated(&self, offset: usize, limit: usize) -> Vec<Room> {
+        self.rooms.read().unwrap().iter().skip(offset).take(limit).cloned().collect()
+    }
+
+    /// Get the number of rooms this store knows about.
+    pub fn room_count(&self) -> usize {
+        self.rooms.read().unwrap().len()
+    }
+
+    /// Count the rooms this store knows about, grouped by state.
+    pub fn room_counts(&self) -> RoomStateCounts {
+        let mut counts = RoomStateCounts::default();
+
+        for room in self.rooms.read().unwrap().iter() {
+            match room.state() {
+                RoomState::Joined => counts.joined += 1,
+                RoomState::Invited => counts.invited += 1,
+                RoomState::Left => counts.left += 1,
+            }
+        }
+
+        counts
+    }
+
     /// Get all the rooms this store knows about, filtered by state.
     pub fn rooms_filtered(&self, filter: RoomStateFilter) -> Vec<Room> {
         self.rooms
@@ -240,6 +290,42 @@ impl Store {
         self.rooms.read().unwrap().get(room_id).cloned()
     }
 
+    /// Get the rooms with the given `RoomId`s, in the same order.
+    ///
+    /// The lock on the rooms is only taken once for all the rooms.
+    pub fn rooms_by_id(&self, room_ids: &[OwnedRoomId]) -> Vec<Option<Room>> {
+        let rooms = self.rooms.read().unwrap();
+        room_ids.iter().map(|room_id| rooms.get(room_id).cloned()).collect()
+    }
+
+    /// Check whether the room with the given `RoomId` is encrypted, without
+    /// cloning the `Room`.
+    pub fn is_room_encrypted(&self, room_id: &RoomId) -> Option<bool> {
+        self.rooms.read().unwrap().get(room_id).map(|room| room.is_encrypted())
+    }
+
+    /// Lookup the `Room` for the given `RoomId`, or create a new one that is
+    /// not added to the store.
+    ///
+    /// The new room is only added to the store by
+    /// [`Store::get_or_create_room`], which allows to process a room without
+    /// leaving a trace in memory until its changes are saved.
+    pub(crate) fn get_or_new_room(
+        &self,
+        room_id: &RoomId,
+        room_type: RoomState,
+        roominfo_update_sender: broadcast::Sender<RoomInfoUpdate>,
+    ) -> Room {
+        if let Some(room) = self.room(room_id) {
+            return room;
+        }
+
+        let user_id =
+            &self.session_meta.get().expect("Creating room while not being logged in").user_id;
+
+        Room::new(user_id, self.inner.clone(), room_id, room_type, roominfo_update_sender)
+    }
+
     /// Lookup the `Room` for the given `RoomId`, or create one, if it didn't
     /// exist yet in the store
     pub fn get_or_create_room(
@@ -326,6 +412,35 @@ pub struct StateChanges {
     /// A map from room id to a map of a display name and a set of user ids that
     /// share that display name in the given room.
     pub ambiguity_maps: BTreeMap<OwnedRoomId, BTreeMap<String, BTreeSet<OwnedUserId>>>,
+
+    /// A map of `RoomId` to the users that are currently typing in the room.
+    ///
+    /// This is not persisted in the store, it is only used to notify the
+    /// subscribers once the changes have been saved successfully.
+    pub typing: BTreeMap<OwnedRoomId, Vec<OwnedUserId>>,
+
+    /// The timeline events, with the ID of their room, for which a handler was
+    /// registered with `BaseClient::register_timeline_event_handler`.
+    ///
+    /// This is not persisted in the store, it is only used to call the handlers
+    /// once the changes have been saved successfully.
+    pub timeline_events: Vec<(OwnedRoomId, Raw<AnySyncTimelineEvent>)>,
+
+    /// The users whose devices changed.
+    ///
+    /// This is not persisted in the store, it is only used to notify the
+    /// subscribers of `BaseClient::subscribe_to_device_list_changes` once the
+    /// changes have been saved successfully.
+    pub changed_devices: Vec<OwnedUserId>,
+
+    /// A map of `RoomId` to the new power levels of the room, when the content
+    /// of its `m.room.power_levels` event changed.
+    ///
+    /// This is not persisted in the store, the event itself is saved in
+    /// `state`. It is only used to notify the subscribers of
+    /// `Room::subscribe_to_power_level_changes` once the changes have been
+    /// saved successfully.
+    pub power_levels: BTreeMap<OwnedRoomId, RoomPowerLevels>,
 }
 
 impl StateChanges {
@@ -417,6 +532,124 @@ impl StateChanges {
     pub fn add_receipts(&mut self, room_id: &RoomId, event: ReceiptEventContent) {
         self.receipts.insert(room_id.to_owned(), event);
     }
+
+    /// Update the `StateChanges` struct with the users that are currently
+    /// typing in the given room.
+    pub fn add_typing(&mut self, room_id: &RoomId, user_ids: Vec<OwnedUserId>) {
+        self.typing.insert(room_id.to_owned(), user_ids);
+    }
+
+    /// Update the `StateChanges` struct with a timeline event of the given room
+    /// that should be passed to the timeline event handlers.
+    pub fn add_timeline_event(&mut self, room_id: &RoomId, event: Raw<AnySyncTimelineEvent>) {
+        self.timeline_events.push((room_id.to_owned(), event));
+    }
+
+    /// Get a minimal summary of these changes.
+    pub fn summary(&self) -> StateChangesSummary {
+        let room_ids = self
+            .state
+            .keys()
+            .chain(self.stripped_state.keys())
+            .chain(self.room_account_data.keys())
+            .chain(self.room_infos.keys())
+            .chain(self.receipts.keys())
+            .chain(self.redactions.keys())
+            .chain(self.profiles.keys())
+            .cloned()
+            .collect();
+
+        let mut event_type_counts = BTreeMap::<String, usize>::new();
+
+        for events in self.state.values().chain(self.stripped_state.values()) {
+            for (event_type, events) in events {
+                *event_type_counts.entry(event_type.to_string()).or_default() += events.len();
+            }
+        }
+
+        for events in self.room_account_data.values() {
+            for event_type in events.keys() {
+                *event_type_counts.entry(event_type.to_string()).or_default() += 1;
+            }
+        }
+
+        for event_type in self.account_data.keys() {
+            *event_type_counts.entry(event_type.to_string()).or_default() += 1;
+        }
+
+        StateChangesSummary { room_ids, event_type_counts }
+    }
+}
+
+/// A portable snapshot of the state of a client, to help reproducing bugs.
+///
+/// It can be serialized, to be shared, and imported in another client.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// The sync token of the client.
+    pub sync_token: Option<String>,
+    /// The info of all the rooms the client knows about.
+    pub room_infos: Vec<RoomInfo>,
+}
+
+/// An error that happened when the client tried to save changes in the state
+/// store, see [`BaseClient::subscribe_to_store_errors`].
+///
+/// [`BaseClient::subscribe_to_store_errors`]: crate::BaseClient::subscribe_to_store_errors
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StoreErrorEvent {
+    /// The name of the operation of the client that failed, e.g.
+    /// `receive_sync_response`.
+    pub operation: &'static str,
+    /// The message of the error returned by the store.
+    pub message: String,
+}
+
+/// The result of the validation of the store, listing the inconsistencies that
+/// were found.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreValidationReport {
+    /// The inconsistencies that were found.
+    pub issues: Vec<StoreValidationIssue>,
+}
+
+impl StoreValidationReport {
+    /// Whether no inconsistency was found.
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// An inconsistency found in the store.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum StoreValidationIssue {
+    /// The room has no `m.room.member` event for our own user.
+    MissingOwnMember {
+        /// The ID of the room.
+        room_id: OwnedRoomId,
+    },
+
+    /// The `m.room.member` event of our own user contradicts the state of the
+    /// room.
+    OwnMembershipMismatch {
+        /// The ID of the room.
+        room_id: OwnedRoomId,
+        /// The state of the room.
+        room_state: RoomState,
+        /// The membership in the `m.room.member` event of our own user.
+        membership: MembershipState,
+    },
+}
+
+/// A minimal summary of the [`StateChanges`] that were saved in the store.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateChangesSummary {
+    /// The rooms that were touched by the changes.
+    pub room_ids: BTreeSet<OwnedRoomId>,
+    /// The number of state and account data events that changed, per event
+    /// type.
+    pub event_type_counts: BTreeMap<String, usize>,
 }
 
 /// Configuration for the state store and, when `encryption` is enabled, for the
@@ -434,6 +667,7 @@ pub struct StoreConfig {
     #[cfg(feature = "e2e-encryption")]
     pub(crate) crypto_store: Arc<DynCryptoStore>,
     pub(crate) state_store: Arc<DynStateStore>,
+    pub(crate) retain_left_rooms_state: bool,
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -451,6 +685,7 @@ impl StoreConfig {
             #[cfg(feature = "e2e-encryption")]
             crypto_store: matrix_sdk_crypto::store::MemoryStore::new().into_crypto_store(),
             state_store: Arc::new(MemoryStore::new()),
+            retain_left_rooms_state: false,
         }
     }
 
@@ -468,6 +703,22 @@ impl StoreConfig {
         self.state_store = store.into_state_store();
         self
     }
+
+    /// Set whether the rooms we left should keep their final state.
+    ///
+    /// By default, when we leave a room, its state and its members are
+    /// considered outdated, since our own member event changed. When this is
+    /// set to `true`, they are kept as they were when we left the room, which
+    /// is useful to show the room in a "recently left" section.
+    ///
+    /// This applies to the rooms left with `BaseClient::room_left` and to the
+    /// rooms received in the `leave` section of a sync response. It only
+    /// concerns the state and the members saved in the store: the in-memory
+    /// snapshots of `Room::members_at` are always dropped when a room is left.
+    pub fn retain_left_rooms_state(mut self, retain: bool) -> Self {
+        self.retain_left_rooms_state = retain;
+        self
+    }
 }
 
 impl Default for StoreConfig {
@@ -475,3 +726,113 @@ impl Default for StoreConfig {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_test::{
+        async_test, InvitedRoomBuilder, JoinedRoomBuilder, LeftRoomBuilder, StateTestEvent,
+        SyncResponseBuilder,
+    };
+    use ruma::room_id;
+
+    use crate::{
+        test_utils::{logged_in_base_client, receive_joined_room},
+        RoomState, RoomStateCounts,
+    };
+
+    #[async_test]
+    async fn test_rooms_paginated() {
+        let client = logged_in_base_client(None).await;
+        let store = &client.store;
+        assert_eq!(store.room_count(), 0);
+        assert!(store.rooms_paginated(0, 10).is_empty());
+
+        for room_id in [
+            room_id!("!a:example.org"),
+            room_id!("!b:example.org"),
+            room_id!("!c:example.org"),
+            room_id!("!d:example.org"),
+            room_id!("!e:example.org"),
+        ] {
+            client.get_or_create_room(room_id, RoomState::Joined);
+        }
+        assert_eq!(store.room_count(), 5);
+
+        let all_room_ids: Vec<_> =
+            store.rooms().iter().map(|room| room.room_id().to_owned()).collect();
+        let page = |offset, limit| {
+            store
+                .rooms_paginated(offset, limit)
+                .iter()
+                .map(|room| room.room_id().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(page(0, 2), all_room_ids[0..2]);
+        assert_eq!(page(2, 2), all_room_ids[2..4]);
+        assert_eq!(page(4, 2), all_room_ids[4..]);
+        assert!(page(5, 2).is_empty());
+        assert_eq!(page(0, 10), all_room_ids);
+    }
+
+    #[async_test]
+    async fn test_room_counts() {
+        let client = logged_in_base_client(None).await;
+        assert_eq!(client.store.room_counts(), RoomStateCounts::default());
+
+        let response = SyncResponseBuilder::new()
+            .add_joined_room(JoinedRoomBuilder::new(room_id!("!joined1:example.org")))
+            .add_joined_room(JoinedRoomBuilder::new(room_id!("!joined2:example.org")))
+            .add_invited_room(InvitedRoomBuilder::new(room_id!("!invited:example.org")))
+            .add_left_room(LeftRoomBuilder::new(room_id!("!left:example.org")))
+            .build_sync_response();
+        client.receive_sync_response(response).await.unwrap();
+
+        assert_eq!(client.store.room_counts(), RoomStateCounts { joined: 2, invited: 1, left: 1 });
+    }
+
+    #[async_test]
+    async fn test_rooms_by_id() {
+        let known_a = room_id!("!a:example.org");
+        let known_b = room_id!("!b:example.org");
+        let unknown = room_id!("!unknown:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(known_a)).await;
+        receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(known_b)).await;
+
+        let rooms = client.store.rooms_by_id(&[
+            known_b.to_owned(),
+            unknown.to_owned(),
+            known_a.to_owned(),
+            unknown.to_owned(),
+        ]);
+        let room_ids: Vec<_> =
+            rooms.iter().map(|room| room.as_ref().map(|room| room.room_id())).collect();
+        assert_eq!(room_ids, [Some(known_b), None, Some(known_a), None]);
+
+        assert!(client.store.rooms_by_id(&[]).is_empty());
+    }
+
+    #[async_test]
+    async fn test_is_room_encrypted() {
+        let room_id = room_id!("!test:example.org");
+        let other_room_id = room_id!("!other:example.org");
+        let client = logged_in_base_client(None).await;
+
+        let mut sync_builder = SyncResponseBuilder::new();
+        receive_joined_room(
+            &client,
+            &mut sync_builder,
+            JoinedRoomBuilder::new(room_id).add_state_event(StateTestEvent::Encryption),
+        )
+        .await;
+        receive_joined_room(&client, &mut sync_builder, JoinedRoomBuilder::new(other_room_id))
+            .await;
+
+        assert_eq!(client.store.is_room_encrypted(room_id), Some(true));
+        assert_eq!(client.store.is_room_encrypted(other_room_id), Some(false));
+        assert_eq!(client.store.is_room_encrypted(room_id!("!unknown:example.org")), None);
+    }
+}
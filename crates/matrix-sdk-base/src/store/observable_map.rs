@@ -126,6 +126,11 @@ mod impl_non_wasm32 {
             self.values.iter()
         }
 
+        /// Return the number of values.
+        pub(crate) fn len(&self) -> usize {
+            self.values.len()
+        }
+
         /// Get a [`Stream`] of the values.
         pub(crate) fn stream(&self) -> (Vector<V>, impl Stream<Item = Vec<VectorDiff<V>>>) {
             self.values.subscribe().into_values_and_batched_stream()
@@ -184,6 +189,11 @@ mod impl_wasm32 {
         pub(crate) fn iter(&self) -> impl Iterator<Item = &V> {
             self.0.values()
         }
+
+        /// Return the number of values.
+        pub(crate) fn len(&self) -> usize {
+            self.0.len()
+        }
     }
 }
 
@@ -14,7 +14,10 @@
 
 //! The SDK's representation of the result of a `/sync` request.
 
-use std::{collections::BTreeMap, fmt};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
 
 use matrix_sdk_common::{debug::DebugRawEvent, deserialized_responses::SyncTimelineEvent};
 use ruma::{
@@ -54,6 +57,50 @@ pub struct SyncResponse {
     pub to_device: Vec<Raw<AnyToDeviceEvent>>,
     /// New notifications per room.
     pub notifications: BTreeMap<OwnedRoomId, Vec<Notification>>,
+    /// Whether the global push rules changed.
+    pub push_rules_changed: bool,
+}
+
+impl SyncResponse {
+    /// Merge a subsequent sync response into this one.
+    ///
+    /// The updates of `other` are considered to happen after the ones of
+    /// `self`.
+    pub(crate) fn extend(&mut self, other: SyncResponse) {
+        self.rooms.extend(other.rooms);
+        self.presence.extend(other.presence);
+        self.account_data.extend(other.account_data);
+        self.to_device.extend(other.to_device);
+
+        for (room_id, notifications) in other.notifications {
+            self.notifications.entry(room_id).or_default().extend(notifications);
+        }
+
+        self.push_rules_changed |= other.push_rules_changed;
+    }
+
+    /// Group the notifications of this response per room.
+    ///
+    /// Several notifications triggered by the same event are only counted
+    /// once.
+    pub fn grouped_notifications(&self) -> BTreeMap<OwnedRoomId, NotificationSummary> {
+        self.notifications
+            .iter()
+            .filter_map(|(room_id, notifications)| {
+                let latest = notifications.last()?.clone();
+
+                let mut event_ids = BTreeSet::new();
+                let count = notifications
+                    .iter()
+                    .filter(|notification| {
+                        notification.event_id().map_or(true, |event_id| event_ids.insert(event_id))
+                    })
+                    .count();
+
+                Some((room_id.clone(), NotificationSummary { count, latest }))
+            })
+            .collect()
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -64,6 +111,7 @@ impl fmt::Debug for SyncResponse {
             .field("account_data", &DebugListOfRawEventsNoId(&self.account_data))
             .field("to_device", &DebugListOfRawEventsNoId(&self.to_device))
             .field("notifications", &self.notifications)
+            .field("push_rules_changed", &self.push_rules_changed)
             .finish_non_exhaustive()
     }
 }
@@ -94,6 +142,47 @@ impl RoomUpdates {
             let _ = room.compute_display_name().await;
         }
     }
+
+    /// Merge subsequent room updates into these ones.
+    ///
+    /// A room only appears in the map matching its latest membership.
+    fn extend(&mut self, other: RoomUpdates) {
+        for (room_id, update) in other.leave {
+            self.join.remove(&room_id);
+            self.invite.remove(&room_id);
+
+            match self.leave.remove(&room_id) {
+                Some(mut previous) => {
+                    previous.extend(update);
+                    self.leave.insert(room_id, previous);
+                }
+                None => {
+                    self.leave.insert(room_id, update);
+                }
+            }
+        }
+
+        for (room_id, update) in other.join {
+            self.leave.remove(&room_id);
+            self.invite.remove(&room_id);
+
+            match self.join.remove(&room_id) {
+                Some(mut previous) => {
+                    previous.extend(update);
+                    self.join.insert(room_id, previous);
+                }
+                None => {
+                    self.join.insert(room_id, update);
+                }
+            }
+        }
+
+        for (room_id, update) in other.invite {
+            self.leave.remove(&room_id);
+            self.join.remove(&room_id);
+            self.invite.insert(room_id, update);
+        }
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -156,6 +245,15 @@ impl JoinedRoomUpdate {
     ) -> Self {
         Self { unread_notifications, timeline, state, account_data, ephemeral, ambiguity_changes }
     }
+
+    fn extend(&mut self, other: JoinedRoomUpdate) {
+        self.unread_notifications = other.unread_notifications;
+        self.timeline.extend(other.timeline);
+        self.state.extend(other.state);
+        self.account_data.extend(other.account_data);
+        self.ephemeral.extend(other.ephemeral);
+        self.ambiguity_changes.extend(other.ambiguity_changes);
+    }
 }
 
 /// Counts of unread notifications for a room.
@@ -206,6 +304,13 @@ impl LeftRoomUpdate {
     ) -> Self {
         Self { timeline, state, account_data, ambiguity_changes }
     }
+
+    fn extend(&mut self, other: LeftRoomUpdate) {
+        self.timeline.extend(other.timeline);
+        self.state.extend(other.state);
+        self.account_data.extend(other.account_data);
+        self.ambiguity_changes.extend(other.ambiguity_changes);
+    }
 }
 
 #[cfg(not(tarpaulin_include))]
@@ -239,6 +344,18 @@ impl Timeline {
     pub(crate) fn new(limited: bool, prev_batch: Option<String>) -> Self {
         Self { limited, prev_batch, ..Default::default() }
     }
+
+    /// Append a subsequent timeline to this one.
+    ///
+    /// If the subsequent timeline is limited, there is a gap between both
+    /// timelines so it replaces this one.
+    fn extend(&mut self, other: Timeline) {
+        if other.limited {
+            *self = other;
+        } else {
+            self.events.extend(other.events);
+        }
+    }
 }
 
 struct DebugInvitedRoomUpdates<'a>(&'a BTreeMap<OwnedRoomId, InvitedRoomUpdate>);
@@ -260,6 +377,16 @@ pub struct Notification {
     pub event: RawAnySyncOrStrippedTimelineEvent,
 }
 
+impl Notification {
+    /// The ID of the event that triggered the notification, if any.
+    fn event_id(&self) -> Option<OwnedEventId> {
+        match &self.event {
+            RawAnySyncOrStrippedTimelineEvent::Sync(ev) => ev.get_field("event_id").ok().flatten(),
+            RawAnySyncOrStrippedTimelineEvent::Stripped(_) => None,
+        }
+    }
+}
+
 #[cfg(not(tarpaulin_include))]
 impl fmt::Debug for Notification {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -274,3 +401,100 @@ impl fmt::Debug for Notification {
             .finish()
     }
 }
+
+/// A summary of the notifications of a room in a [`SyncResponse`].
+#[derive(Clone, Debug)]
+pub struct NotificationSummary {
+    /// The number of distinct notifications in the room.
+    pub count: usize,
+
+    /// The most recent notification in the room.
+    pub latest: Notification,
+}
+
+#[cfg(test)]
+mod tests {
+    use matrix_sdk_test::sync_timeline_event;
+    use ruma::{event_id, events::AnySyncStateEvent, push::Action, room_id, serde::Raw};
+    use serde_json::json;
+
+    use super::{JoinedRoomUpdate, LeftRoomUpdate, Notification, SyncResponse};
+    use crate::deserialized_responses::RawAnySyncOrStrippedTimelineEvent;
+
+    fn notification(event_id: &str) -> Notification {
+        Notification {
+            actions: vec![Action::Notify],
+            event: RawAnySyncOrStrippedTimelineEvent::Sync(sync_timeline_event!({
+                "content": { "body": "hello", "msgtype": "m.text" },
+                "event_id": event_id,
+                "origin_server_ts": 151800140,
+                "sender": "@example:localhost",
+                "type": "m.room.message",
+            })),
+        }
+    }
+
+    #[test]
+    fn test_grouped_notifications() {
+        let room_id = room_id!("!room:example.org");
+        let other_room_id = room_id!("!other:example.org");
+
+        let mut response = SyncResponse::default();
+        response.notifications.insert(
+            room_id.to_owned(),
+            vec![notification("$first"), notification("$second"), notification("$second")],
+        );
+        response.notifications.insert(other_room_id.to_owned(), vec![notification("$third")]);
+        response.notifications.insert(room_id!("!empty:example.org").to_owned(), Vec::new());
+
+        let grouped = response.grouped_notifications();
+        assert_eq!(grouped.len(), 2);
+
+        let summary = &grouped[room_id];
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.latest.event_id().as_deref(), Some(event_id!("$second")));
+
+        let summary = &grouped[other_room_id];
+        assert_eq!(summary.count, 1);
+        assert_eq!(summary.latest.event_id().as_deref(), Some(event_id!("$third")));
+    }
+
+    #[test]
+    fn test_sync_response_extend() {
+        let room_id = room_id!("!room:example.org");
+        let left_room_id = room_id!("!left:example.org");
+        let state_event: Raw<AnySyncStateEvent> = Raw::new(&json!({
+            "content": { "name": "A room" },
+            "event_id": "$name",
+            "origin_server_ts": 151800140,
+            "sender": "@example:localhost",
+            "state_key": "",
+            "type": "m.room.name",
+        }))
+        .unwrap()
+        .cast();
+
+        let mut response = SyncResponse::default();
+        let update = JoinedRoomUpdate { state: vec![state_event.clone()], ..Default::default() };
+        response.rooms.join.insert(room_id.to_owned(), update.clone());
+        response.rooms.join.insert(left_room_id.to_owned(), update.clone());
+        response.notifications.insert(room_id.to_owned(), vec![notification("$first")]);
+
+        let mut next = SyncResponse::default();
+        next.rooms.join.insert(room_id.to_owned(), update);
+        next.rooms.leave.insert(left_room_id.to_owned(), LeftRoomUpdate::default());
+        next.notifications.insert(room_id.to_owned(), vec![notification("$second")]);
+        next.push_rules_changed = true;
+
+        response.extend(next);
+
+        // The updates of the same room are merged,
+        assert_eq!(response.rooms.join[room_id].state.len(), 2);
+        assert_eq!(response.notifications[room_id].len(), 2);
+        assert!(response.push_rules_changed);
+
+        // And a room only appears with its latest membership.
+        assert!(!response.rooms.join.contains_key(left_room_id));
+        assert!(response.rooms.leave.contains_key(left_room_id));
+    }
+}
@@ -16,14 +16,53 @@
 
 #![allow(dead_code)]
 
-use ruma::{owned_user_id, UserId};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex as StdMutex,
+    },
+};
 
-use crate::{BaseClient, SessionMeta};
+use async_trait::async_trait;
+use matrix_sdk_test::{JoinedRoomBuilder, SyncResponseBuilder};
+use ruma::{
+    events::{
+        presence::PresenceEvent,
+        receipt::{Receipt, ReceiptThread, ReceiptType},
+        AnyGlobalAccountDataEvent, AnyRoomAccountDataEvent, GlobalAccountDataEventType,
+        RoomAccountDataEventType, StateEventType,
+    },
+    owned_user_id,
+    serde::Raw,
+    EventId, MxcUri, OwnedEventId, OwnedRoomId, OwnedTransactionId, OwnedUserId, RoomId,
+    TransactionId, UserId,
+};
+use tokio::sync::Mutex;
+
+use crate::{
+    deserialized_responses::RawAnySyncOrStrippedState,
+    media::MediaRequest,
+    store::{
+        MemoryStore, QueuedEvent, SerializableEventContent, StateChanges, StateStore,
+        StateStoreDataKey, StateStoreDataValue, StoreConfig, StoreError,
+    },
+    BaseClient, MinimalRoomMemberEvent, Room, RoomInfo, RoomMemberships, SessionMeta,
+};
 
 /// Create a [`BaseClient`] with the given user id, if provided, or an hardcoded
 /// one otherwise.
 pub(crate) async fn logged_in_base_client(user_id: Option<&UserId>) -> BaseClient {
-    let client = BaseClient::new();
+    logged_in_base_client_with_store_config(user_id, StoreConfig::new()).await
+}
+
+/// Create a [`BaseClient`] with the given store config, logged in with the
+/// given user id, if provided, or an hardcoded one otherwise.
+pub(crate) async fn logged_in_base_client_with_store_config(
+    user_id: Option<&UserId>,
+    config: StoreConfig,
+) -> BaseClient {
+    let client = BaseClient::with_store_config(config);
     let user_id =
         user_id.map(|user_id| user_id.to_owned()).unwrap_or_else(|| owned_user_id!("@u:e.uk"));
     client
@@ -36,3 +75,300 @@ pub(crate) async fn logged_in_base_client(user_id: Option<&UserId>) -> BaseClien
         .expect("set_session_meta failed!");
     client
 }
+
+/// Receive a sync response containing only the given joined room with the
+/// [`BaseClient`], and return the [`Room`].
+///
+/// The same `sync_builder` must be used for all the responses of a test, so
+/// that each one has a new sync token and isn't ignored as a duplicate.
+pub(crate) async fn receive_joined_room(
+    client: &BaseClient,
+    sync_builder: &mut SyncResponseBuilder,
+    room: JoinedRoomBuilder,
+) -> Room {
+    let response = sync_builder.add_joined_room(room).build_sync_response();
+    let room_id = response.rooms.join.keys().next().expect("the response has a room").clone();
+
+    client.receive_sync_response(response).await.expect("receive_sync_response failed!");
+    client.get_room(&room_id).expect("the room wasn't created by the sync")
+}
+
+/// A [`StateStore`] wrapping a [`MemoryStore`], whose
+/// [`StateStore::save_changes`] can be made to fail or to wait.
+#[derive(Debug, Default)]
+pub(crate) struct TestStateStore {
+    inner: MemoryStore,
+    /// When set, [`StateStore::save_changes`] returns an error without saving
+    /// anything.
+    pub fail_save_changes: AtomicBool,
+    /// [`StateStore::save_changes`] waits for this lock before saving, so
+    /// tests can hold it to pause a save in the middle of the store write.
+    pub save_changes_gate: Mutex<()>,
+    /// The number of times [`StateStore::save_changes`] has been called.
+    pub save_changes_calls: AtomicUsize,
+    /// The changes that were last saved successfully.
+    pub last_saved_changes: StdMutex<Option<StateChanges>>,
+}
+
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+impl StateStore for TestStateStore {
+    type Error = StoreError;
+
+    async fn get_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+    ) -> Result<Option<StateStoreDataValue>, Self::Error> {
+        self.inner.get_kv_data(key).await
+    }
+
+    async fn set_kv_data(
+        &self,
+        key: StateStoreDataKey<'_>,
+        value: StateStoreDataValue,
+    ) -> Result<(), Self::Error> {
+        self.inner.set_kv_data(key, value).await
+    }
+
+    async fn remove_kv_data(&self, key: StateStoreDataKey<'_>) -> Result<(), Self::Error> {
+        self.inner.remove_kv_data(key).await
+    }
+
+    async fn save_changes(&self, changes: &StateChanges) -> Result<(), Self::Error> {
+        self.save_changes_calls.fetch_add(1, Ordering::SeqCst);
+        let _gate = self.save_changes_gate.lock().await;
+
+        if self.fail_save_changes.load(Ordering::SeqCst) {
+            return Err(StoreError::Backend("save_changes failed on purpose".into()));
+        }
+
+        self.inner.save_changes(changes).await?;
+        *self.last_saved_changes.lock().unwrap() = Some(changes.clone());
+
+        Ok(())
+    }
+
+    async fn get_presence_event(
+        &self,
+        user_id: &UserId,
+    ) -> Result<Option<Raw<PresenceEvent>>, Self::Error> {
+        self.inner.get_presence_event(user_id).await
+    }
+
+    async fn get_presence_events(
+        &self,
+        user_ids: &[OwnedUserId],
+    ) -> Result<Vec<Raw<PresenceEvent>>, Self::Error> {
+        self.inner.get_presence_events(user_ids).await
+    }
+
+    async fn get_state_event(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_key: &str,
+    ) -> Result<Option<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_event(room_id, event_type, state_key).await
+    }
+
+    async fn get_state_events(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_events(room_id, event_type).await
+    }
+
+    async fn get_state_events_for_keys(
+        &self,
+        room_id: &RoomId,
+        event_type: StateEventType,
+        state_keys: &[&str],
+    ) -> Result<Vec<RawAnySyncOrStrippedState>, Self::Error> {
+        self.inner.get_state_events_for_keys(room_id, event_type, state_keys).await
+    }
+
+    async fn get_profile(
+        &self,
+        room_id: &RoomId,
+        user_id: &UserId,
+    ) -> Result<Option<MinimalRoomMemberEvent>, Self::Error> {
+        self.inner.get_profile(room_id, user_id).await
+    }
+
+    async fn get_profiles<'a>(
+        &self,
+        room_id: &RoomId,
+        user_ids: &'a [OwnedUserId],
+    ) -> Result<BTreeMap<&'a UserId, MinimalRoomMemberEvent>, Self::Error> {
+        self.inner.get_profiles(room_id, user_ids).await
+    }
+
+    async fn get_user_ids(
+        &self,
+        room_id: &RoomId,
+        memberships: RoomMemberships,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_user_ids(room_id, memberships).await
+    }
+
+    async fn get_invited_user_ids(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_user_ids(room_id, RoomMemberships::INVITE).await
+    }
+
+    async fn get_joined_user_ids(&self, room_id: &RoomId) -> Result<Vec<OwnedUserId>, Self::Error> {
+        self.inner.get_user_ids(room_id, RoomMemberships::JOIN).await
+    }
+
+    async fn get_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.inner.get_room_infos().await
+    }
+
+    #[allow(deprecated)]
+    async fn get_stripped_room_infos(&self) -> Result<Vec<RoomInfo>, Self::Error> {
+        self.inner.get_stripped_room_infos().await
+    }
+
+    async fn get_users_with_display_name(
+        &self,
+        room_id: &RoomId,
+        display_name: &str,
+    ) -> Result<BTreeSet<OwnedUserId>, Self::Error> {
+        self.inner.get_users_with_display_name(room_id, display_name).await
+    }
+
+    async fn get_users_with_display_names<'a>(
+        &self,
+        room_id: &RoomId,
+        display_names: &'a [String],
+    ) -> Result<BTreeMap<&'a str, BTreeSet<OwnedUserId>>, Self::Error> {
+        self.inner.get_users_with_display_names(room_id, display_names).await
+    }
+
+    async fn get_account_data_event(
+        &self,
+        event_type: GlobalAccountDataEventType,
+    ) -> Result<Option<Raw<AnyGlobalAccountDataEvent>>, Self::Error> {
+        self.inner.get_account_data_event(event_type).await
+    }
+
+    async fn get_room_account_data_event(
+        &self,
+        room_id: &RoomId,
+        event_type: RoomAccountDataEventType,
+    ) -> Result<Option<Raw<AnyRoomAccountDataEvent>>, Self::Error> {
+        self.inner.get_room_account_data_event(room_id, event_type).await
+    }
+
+    async fn get_user_room_receipt_event(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        user_id: &UserId,
+    ) -> Result<Option<(OwnedEventId, Receipt)>, Self::Error> {
+        self.inner.get_user_room_receipt_event(room_id, receipt_type, thread, user_id).await
+    }
+
+    async fn get_event_room_receipt_events(
+        &self,
+        room_id: &RoomId,
+        receipt_type: ReceiptType,
+        thread: ReceiptThread,
+        event_id: &EventId,
+    ) -> Result<Vec<(OwnedUserId, Receipt)>, Self::Error> {
+        self.inner.get_event_room_receipt_events(room_id, receipt_type, thread, event_id).await
+    }
+
+    async fn get_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.get_custom_value(key).await
+    }
+
+    async fn set_custom_value(
+        &self,
+        key: &[u8],
+        value: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.set_custom_value(key, value).await
+    }
+
+    async fn remove_custom_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.remove_custom_value(key).await
+    }
+
+    async fn add_media_content(
+        &self,
+        request: &MediaRequest,
+        content: Vec<u8>,
+    ) -> Result<(), Self::Error> {
+        self.inner.add_media_content(request, content).await
+    }
+
+    async fn get_media_content(
+        &self,
+        request: &MediaRequest,
+    ) -> Result<Option<Vec<u8>>, Self::Error> {
+        self.inner.get_media_content(request).await
+    }
+
+    async fn remove_media_content(&self, request: &MediaRequest) -> Result<(), Self::Error> {
+        self.inner.remove_media_content(request).await
+    }
+
+    async fn remove_media_content_for_uri(&self, uri: &MxcUri) -> Result<(), Self::Error> {
+        self.inner.remove_media_content_for_uri(uri).await
+    }
+
+    async fn remove_room(&self, room_id: &RoomId) -> Result<(), Self::Error> {
+        self.inner.remove_room(room_id).await
+    }
+
+    async fn save_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: OwnedTransactionId,
+        content: SerializableEventContent,
+    ) -> Result<(), Self::Error> {
+        self.inner.save_send_queue_event(room_id, transaction_id, content).await
+    }
+
+    async fn update_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        content: SerializableEventContent,
+    ) -> Result<bool, Self::Error> {
+        self.inner.update_send_queue_event(room_id, transaction_id, content).await
+    }
+
+    async fn remove_send_queue_event(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+    ) -> Result<bool, Self::Error> {
+        self.inner.remove_send_queue_event(room_id, transaction_id).await
+    }
+
+    async fn load_send_queue_events(
+        &self,
+        room_id: &RoomId,
+    ) -> Result<Vec<QueuedEvent>, Self::Error> {
+        self.inner.load_send_queue_events(room_id).await
+    }
+
+    async fn update_send_queue_event_status(
+        &self,
+        room_id: &RoomId,
+        transaction_id: &TransactionId,
+        wedged: bool,
+    ) -> Result<(), Self::Error> {
+        self.inner.update_send_queue_event_status(room_id, transaction_id, wedged).await
+    }
+
+    async fn load_rooms_with_unsent_events(&self) -> Result<Vec<OwnedRoomId>, Self::Error> {
+        self.inner.load_rooms_with_unsent_events().await
+    }
+}
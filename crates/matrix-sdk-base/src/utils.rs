@@ -98,6 +98,22 @@ where
         }
     }
 
+    /// Whether this is the same event as `other`, in the same redaction state.
+    ///
+    /// Events without an ID, like the ones from invited rooms, are never
+    /// considered to be the same event.
+    pub(crate) fn is_same_event(&self, other: &Self) -> bool {
+        match (self, other) {
+            (MinimalStateEvent::Original(a), MinimalStateEvent::Original(b)) => {
+                a.event_id.is_some() && a.event_id == b.event_id
+            }
+            (MinimalStateEvent::Redacted(a), MinimalStateEvent::Redacted(b)) => {
+                a.event_id.is_some() && a.event_id == b.event_id
+            }
+            _ => false,
+        }
+    }
+
     /// Returns the inner event, if it isn't redacted.
     pub fn as_original(&self) -> Option<&OriginalMinimalStateEvent<C>> {
         match self {
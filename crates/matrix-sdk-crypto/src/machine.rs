@@ -1301,6 +1301,49 @@ impl OlmMachine {
         Ok((events, room_key_updates))
     }
 
+    /// Handle a single to-device event that was received outside of a sync
+    /// response.
+    ///
+    /// The event is decrypted and handled like the to-device events of a sync
+    /// response, but the one-time key counts, the fallback keys and the device
+    /// lists known by the machine are left untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The to-device event that should be handled.
+    ///
+    /// # Returns
+    ///
+    /// A tuple of (the decrypted event, or the event as-is if it isn't
+    /// encrypted or couldn't be decrypted, updated room keys).
+    #[instrument(skip_all)]
+    pub async fn receive_out_of_band_to_device_event(
+        &self,
+        event: Raw<AnyToDeviceEvent>,
+    ) -> OlmResult<(Raw<AnyToDeviceEvent>, Vec<RoomKeyInfo>)> {
+        let mut store_transaction = self.inner.store.transaction().await;
+        let mut changes = Changes::default();
+
+        let event =
+            Box::pin(self.receive_to_device_event(&mut store_transaction, &mut changes, event))
+                .await;
+
+        let changed_sessions = self
+            .inner
+            .key_request_machine
+            .collect_incoming_key_requests(store_transaction.cache())
+            .await?;
+        changes.sessions.extend(changed_sessions);
+
+        let room_key_updates: Vec<_> =
+            changes.inbound_group_sessions.iter().map(RoomKeyInfo::from).collect();
+
+        self.store().save_changes(changes).await?;
+        store_transaction.commit().await?;
+
+        Ok((event, room_key_updates))
+    }
+
     pub(crate) async fn preprocess_sync_changes(
         &self,
         transaction: &mut StoreTransaction,
@@ -4501,6 +4544,43 @@ pub(crate) mod tests {
         );
     }
 
+    #[async_test]
+    async fn test_receive_out_of_band_to_device_event() {
+        let (alice, bob) = get_machine_pair_with_session(alice_id(), user_id(), false).await;
+
+        let device = alice.get_device(bob.user_id(), bob.device_id(), None).await.unwrap().unwrap();
+        let raw_encrypted = device
+            .encrypt_event_raw("m.new_device", &json!({ "device_id": "XYZABCDE" }))
+            .await
+            .unwrap();
+
+        let event = json_convert(&json!({
+            "content": raw_encrypted,
+            "sender": alice.user_id(),
+            "type": "m.room.encrypted",
+        }))
+        .unwrap();
+
+        let uploaded_key_count =
+            bob.store().cache().await.unwrap().account().await.unwrap().uploaded_key_count();
+
+        let (decrypted, room_key_updates) =
+            bob.receive_out_of_band_to_device_event(event).await.unwrap();
+
+        assert_eq!(decrypted.deserialize().unwrap().event_type().to_string(), "m.new_device");
+        assert_eq!(
+            decrypted.get_field::<String>("sender").unwrap().as_deref(),
+            Some(alice.user_id().as_str())
+        );
+        assert!(room_key_updates.is_empty());
+
+        // The one-time key counts weren't touched.
+        assert_eq!(
+            bob.store().cache().await.unwrap().account().await.unwrap().uploaded_key_count(),
+            uploaded_key_count
+        );
+    }
+
     #[async_test]
     async fn test_send_encrypted_to_device_no_session() {
         let (alice, bob, _) = get_machine_pair(alice_id(), user_id(), false).await;
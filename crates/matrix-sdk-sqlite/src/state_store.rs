@@ -996,6 +996,14 @@ impl StateStore for SqliteStateStore {
                     redactions,
                     stripped_state,
                     ambiguity_maps,
+                    // Typing notifications are not persisted.
+                    typing: _,
+                    // The timeline events are only passed to the handlers.
+                    timeline_events: _,
+                    // The device list changes are only sent to the subscribers.
+                    changed_devices: _,
+                    // The power levels are saved with the other state events.
+                    power_levels: _,
                 } = changes;
 
                 if let Some(sync_token) = sync_token {
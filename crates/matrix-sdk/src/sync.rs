@@ -52,14 +52,30 @@ pub struct SyncResponse {
     pub to_device: Vec<Raw<AnyToDeviceEvent>>,
     /// New notifications per room.
     pub notifications: BTreeMap<OwnedRoomId, Vec<Notification>>,
+    /// Whether the global push rules changed.
+    pub push_rules_changed: bool,
 }
 
 impl SyncResponse {
     pub(crate) fn new(next_batch: String, base_response: BaseSyncResponse) -> Self {
-        let BaseSyncResponse { rooms, presence, account_data, to_device, notifications } =
-            base_response;
-
-        Self { next_batch, rooms, presence, account_data, to_device, notifications }
+        let BaseSyncResponse {
+            rooms,
+            presence,
+            account_data,
+            to_device,
+            notifications,
+            push_rules_changed,
+        } = base_response;
+
+        Self {
+            next_batch,
+            rooms,
+            presence,
+            account_data,
+            to_device,
+            notifications,
+            push_rules_changed,
+        }
     }
 }
 
@@ -72,6 +88,7 @@ impl fmt::Debug for SyncResponse {
             .field("account_data", &DebugListOfRawEventsNoId(&self.account_data))
             .field("to_device", &DebugListOfRawEventsNoId(&self.to_device))
             .field("notifications", &self.notifications)
+            .field("push_rules_changed", &self.push_rules_changed)
             .finish_non_exhaustive()
     }
 }
@@ -152,7 +169,8 @@ impl Client {
         &self,
         response: &BaseSyncResponse,
     ) -> Result<()> {
-        let BaseSyncResponse { rooms, presence, account_data, to_device, notifications } = response;
+        let BaseSyncResponse { rooms, presence, account_data, to_device, notifications, .. } =
+            response;
 
         let now = Instant::now();
         self.handle_sync_events(HandlerKind::GlobalAccountData, None, account_data).await?;